@@ -0,0 +1,77 @@
+use aoc2021::bits::{bench, Bits, BitsError};
+use criterion::measurement::WallTime;
+use criterion::{
+    black_box, criterion_group, criterion_main, BenchmarkGroup, BenchmarkId, Criterion,
+};
+
+const WIDTHS: [usize; 6] = [3, 8, 13, 32, 57, 64];
+
+fn sample() -> Vec<u8> {
+    (0..4096u32).map(|i| (i * 31 % 251) as u8).collect()
+}
+
+fn take(c: &mut Criterion) {
+    let bytes = sample();
+    let mut group = c.benchmark_group("take");
+    for width in WIDTHS {
+        group.bench_with_input(BenchmarkId::new("bytes", width), &width, |b, &width| {
+            b.iter(|| {
+                let mut bits = Bits::new(&bytes);
+                bits.skip(3).unwrap();
+                while let Ok(value) = bits.take(black_box(width)) {
+                    black_box(value);
+                }
+            })
+        });
+        group.bench_with_input(BenchmarkId::new("uint", width), &width, |b, &width| {
+            b.iter(|| {
+                let mut bits = Bits::new(&bytes);
+                bits.skip(3).unwrap();
+                while let Ok(value) = bits.take_u64(black_box(width)) {
+                    black_box(value);
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+type Grab<T> = fn(&[u8], u8, usize) -> Result<T, BitsError>;
+
+// Reads the whole sample from 3 bits in, one `width` at a time.
+fn grab_all<T>(group: &mut BenchmarkGroup<WallTime>, name: &str, bytes: &[u8], grab: Grab<T>) {
+    for width in WIDTHS {
+        group.bench_with_input(BenchmarkId::new(name, width), &width, |b, &width| {
+            b.iter(|| {
+                for position in (3..=bytes.len() * 8 - width).step_by(width) {
+                    let slice = &bytes[position / 8..];
+                    black_box(grab(slice, (position % 8) as u8, black_box(width)).unwrap());
+                }
+            })
+        });
+    }
+}
+
+// Compares the word-at-a-time reads with the byte-at-a-time ones they replaced.
+fn grab(c: &mut Criterion) {
+    let bytes = sample();
+    let mut group = c.benchmark_group("grab");
+    grab_all(&mut group, "bits/word", &bytes, bench::grab_bits);
+    grab_all(
+        &mut group,
+        "bits/bytewise",
+        &bytes,
+        bench::grab_bits_bytewise,
+    );
+    grab_all(&mut group, "uint/word", &bytes, bench::grab_uint);
+    grab_all(
+        &mut group,
+        "uint/bytewise",
+        &bytes,
+        bench::grab_uint_bytewise,
+    );
+    group.finish();
+}
+
+criterion_group!(benches, take, grab);
+criterion_main!(benches);
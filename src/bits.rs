@@ -1,129 +1,1153 @@
-use thiserror::Error;
+#[cfg(feature = "alloc")]
+use alloc::{vec, vec::Vec};
+use core::convert::TryFrom;
+use core::fmt::{self, Debug};
+use core::marker::PhantomData;
+#[cfg(feature = "std")]
+use std::io::{self, Read};
 
-#[derive(Clone, Copy, Debug, PartialEq)]
-pub struct Bits<'i> {
+// Reads bits from a byte slice, MSB-first within each byte unless another `BitOrder` is given.
+// Positions are given in bits, relative to the start of the reader, which for a sub-reader is
+// where it was split off.
+#[derive(Clone, Copy)]
+pub struct Bits<'i, O: BitOrder = Msb0> {
     inner: &'i [u8],
-    offset: u8,
+    start: usize,
+    position: usize,
+    end: usize,
+    // The word last loaded by `take_uint`, reused while the reads stay within it.
+    cache: Option<CachedWord>,
+    order: PhantomData<O>,
+}
+
+// Eight bytes of the slice, starting at byte `index`, padded with zeros past its end.
+#[derive(Clone, Copy)]
+struct CachedWord {
+    index: usize,
+    bytes: [u8; 8],
+}
+
+// The cache is not part of a reader's state, so readers at the same place are equal whatever
+// they last loaded.
+impl<O: BitOrder> PartialEq for Bits<'_, O> {
+    fn eq(&self, other: &Self) -> bool {
+        self.inner == other.inner
+            && self.start == other.start
+            && self.position == other.position
+            && self.end == other.end
+    }
+}
+
+impl<O: BitOrder> Debug for Bits<'_, O> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Bits")
+            .field("inner", &self.inner)
+            .field("start", &self.start)
+            .field("position", &self.position)
+            .field("end", &self.end)
+            .finish()
+    }
 }
 
 impl<'i> Bits<'i> {
     pub fn new(slice: &'i [u8]) -> Bits<'i> {
+        Bits::with_order(slice)
+    }
+
+    // Decodes hex digits of either case into the start of `buffer`, returning a reader over
+    // exactly four bits per digit, so an odd number of digits leaves the last byte half used.
+    pub fn from_hex(input: &str, buffer: &'i mut [u8]) -> Result<Bits<'i>, BitsError> {
+        let needed = byte_count(input.len() * 4);
+        if buffer.len() < needed {
+            return Err(BitsError::BufferTooSmall {
+                needed,
+                len: buffer.len(),
+            });
+        }
+        let buffer = &mut buffer[..needed];
+        buffer.fill(0);
+        for (index, digit) in input.bytes().enumerate() {
+            let nibble = (digit as char)
+                .to_digit(16)
+                .ok_or(BitsError::InvalidHex { index })? as u8;
+            buffer[index / 2] |= if index % 2 == 0 { nibble << 4 } else { nibble };
+        }
+        let mut bits = Bits::new(buffer);
+        bits.end = input.len() * 4;
+        Ok(bits)
+    }
+}
+
+impl<'i, O: BitOrder> Bits<'i, O> {
+    pub fn with_order(slice: &'i [u8]) -> Bits<'i, O> {
         Bits {
             inner: slice,
-            offset: 0,
+            start: 0,
+            position: 0,
+            end: slice.len() * 8,
+            cache: None,
+            order: PhantomData,
         }
     }
 
+    #[cfg(feature = "alloc")]
     pub fn take(&mut self, bits: usize) -> Result<Vec<u8>, BitsError> {
-        let (slice, offset, bytes) = grab_bits(self.inner, self.offset, bits)?;
-        self.inner = slice;
-        self.offset = offset;
+        let bytes = self.peek(bits)?;
+        self.position += bits;
         Ok(bytes)
     }
 
+    #[cfg(feature = "alloc")]
     pub fn peek(&self, bits: usize) -> Result<Vec<u8>, BitsError> {
-        let (_, _, bytes) = grab_bits(self.inner, self.offset, bits)?;
+        self.check(bits)?;
+        Ok(read_vec(bits, |out| {
+            O::read_into(self.inner, self.position, bits, out)
+        }))
+    }
+
+    // Like `take`, but writes the bytes to the start of `buffer` rather than allocating,
+    // returning the part of the buffer written.
+    pub fn take_into<'b>(
+        &mut self,
+        bits: usize,
+        buffer: &'b mut [u8],
+    ) -> Result<&'b [u8], BitsError> {
+        let bytes = self.peek_into(bits, buffer)?;
+        self.position += bits;
+        Ok(bytes)
+    }
+
+    pub fn peek_into<'b>(&self, bits: usize, buffer: &'b mut [u8]) -> Result<&'b [u8], BitsError> {
+        self.check(bits)?;
+        let needed = byte_count(bits);
+        if buffer.len() < needed {
+            return Err(BitsError::BufferTooSmall {
+                needed,
+                len: buffer.len(),
+            });
+        }
+        let bytes = &mut buffer[..needed];
+        O::read_into(self.inner, self.position, bits, bytes);
         Ok(bytes)
     }
 
-    // Length is returned as (bytes, offset), where the bytes is the
-    // total length in bytes, and the offset is the number of bits offset
-    // from that. The length in bits is therefore bytes * 8 - offset.
-    pub fn len(&self) -> (usize, u8) {
-        (self.inner.len(), self.offset)
+    // Reads up to the width of `T` bits as an unsigned integer, without allocating. Reads of
+    // up to 56 bits come from a cached word, which is only reloaded once they run past it.
+    pub fn take_uint<T: UInt>(&mut self, bits: usize) -> Result<T, BitsError> {
+        self.check_uint::<T>(bits)?;
+        let value = if bits <= WORD_BITS {
+            self.read_cached(bits) as u128
+        } else {
+            O::read_uint(self.inner, self.position, bits)
+        };
+        self.position += bits;
+        Ok(T::truncate(value))
+    }
+
+    pub fn peek_uint<T: UInt>(&self, bits: usize) -> Result<T, BitsError> {
+        self.check_uint::<T>(bits)?;
+        Ok(T::truncate(O::read_uint(self.inner, self.position, bits)))
+    }
+
+    pub fn take_u8(&mut self, bits: usize) -> Result<u8, BitsError> {
+        self.take_uint(bits)
+    }
+
+    pub fn take_u16(&mut self, bits: usize) -> Result<u16, BitsError> {
+        self.take_uint(bits)
+    }
+
+    pub fn take_u32(&mut self, bits: usize) -> Result<u32, BitsError> {
+        self.take_uint(bits)
+    }
+
+    pub fn take_u64(&mut self, bits: usize) -> Result<u64, BitsError> {
+        self.take_uint(bits)
+    }
+
+    pub fn take_u128(&mut self, bits: usize) -> Result<u128, BitsError> {
+        self.take_uint(bits)
+    }
+
+    // Reads groups of a continuation bit followed by `group_bits` data bits, until a group
+    // with the continuation bit unset. On failure the reader is left where it was.
+    pub fn take_varint<T: UInt>(
+        &mut self,
+        group_bits: usize,
+        order: GroupOrder,
+    ) -> Result<T, BitsError> {
+        if group_bits == 0 || group_bits > 127 {
+            return Err(BitsError::TooWide {
+                requested: group_bits,
+                max: 127,
+            });
+        }
+        let mut reader = *self;
+        let mut value = 0u128;
+        let mut shift = 0;
+        loop {
+            let more = reader.take_u8(1)? == 1;
+            let group = reader.take_u128(group_bits)?;
+            match order {
+                GroupOrder::MostSignificantFirst => {
+                    // The bit length of the value once this group is appended.
+                    let len = match value {
+                        0 => 128 - group.leading_zeros() as usize,
+                        _ => 128 - value.leading_zeros() as usize + group_bits,
+                    };
+                    if len > T::BITS {
+                        return Err(BitsError::Overflow { max: T::BITS });
+                    }
+                    value = value << group_bits | group;
+                }
+                GroupOrder::LeastSignificantFirst => {
+                    if group != 0 {
+                        if shift + (128 - group.leading_zeros() as usize) > T::BITS {
+                            return Err(BitsError::Overflow { max: T::BITS });
+                        }
+                        value |= group << shift;
+                    }
+                    shift += group_bits;
+                }
+            }
+            if !more {
+                break;
+            }
+        }
+        *self = reader;
+        Ok(T::truncate(value))
+    }
+
+    // Reads an unsigned LEB128 value: bytes holding 7 data bits, least significant first, with
+    // the high bit set on all but the last.
+    pub fn take_leb128<T: UInt>(&mut self) -> Result<T, BitsError> {
+        let mut reader = *self;
+        let mut value = 0u128;
+        let mut shift = 0;
+        loop {
+            let byte = reader.take_u8(8)?;
+            let group = (byte & 0x7F) as u128;
+            if group != 0 {
+                if shift + (128 - group.leading_zeros() as usize) > T::BITS {
+                    return Err(BitsError::Overflow { max: T::BITS });
+                }
+                value |= group << shift;
+            }
+            shift += 7;
+            if byte & 0x80 == 0 {
+                break;
+            }
+        }
+        *self = reader;
+        Ok(T::truncate(value))
+    }
+
+    // Reads an Elias gamma code: N zero bits, then the N + 1 bits of a value of at least 1,
+    // starting with its leading one.
+    pub fn take_elias_gamma<T: UInt>(&mut self) -> Result<T, BitsError> {
+        let mut reader = *self;
+        let mut zeros = 0;
+        while reader.take_u8(1)? == 0 {
+            zeros += 1;
+        }
+        if zeros >= T::BITS {
+            return Err(BitsError::Overflow { max: T::BITS });
+        }
+        let rest = reader.take_u128(zeros)?;
+        *self = reader;
+        Ok(T::truncate(1 << zeros | rest))
+    }
+
+    pub fn skip(&mut self, bits: usize) -> Result<(), BitsError> {
+        self.check(bits)?;
+        self.position += bits;
+        Ok(())
+    }
+
+    // Moves to the given position, which may be anywhere up to the end of the reader.
+    pub fn seek(&mut self, position: usize) -> Result<(), BitsError> {
+        let len = self.end - self.start;
+        if position > len {
+            return Err(BitsError::OutOfBounds { position, len });
+        }
+        self.position = self.start + position;
+        Ok(())
+    }
+
+    // Splits off a reader over the next `bits` bits, and advances past them.
+    pub fn split(&mut self, bits: usize) -> Result<Bits<'i, O>, BitsError> {
+        self.check(bits)?;
+        let sub_reader = Bits {
+            inner: self.inner,
+            start: self.position,
+            position: self.position,
+            end: self.position + bits,
+            cache: None,
+            order: PhantomData,
+        };
+        self.position += bits;
+        Ok(sub_reader)
+    }
+
+    pub fn position(&self) -> usize {
+        self.position - self.start
+    }
+
+    // Number of bits remaining.
+    pub fn len(&self) -> usize {
+        self.end - self.position
     }
 
     pub fn is_empty(&self) -> bool {
-        self.inner.is_empty()
+        self.len() == 0
+    }
+
+    // Iterates over the remaining bits, leaving this reader where it is.
+    pub fn iter(&self) -> Iter<'i, O> {
+        Iter { bits: *self }
+    }
+
+    // Iterates over the remaining bits in chunks of `width` bits, read as integers. Any bits
+    // left over after the last whole chunk are available from `Chunks::remainder`.
+    pub fn chunks<T: UInt>(&self, width: usize) -> Result<Chunks<'i, T, O>, BitsError> {
+        if width == 0 || width > T::BITS {
+            return Err(BitsError::TooWide {
+                requested: width,
+                max: T::BITS,
+            });
+        }
+        Ok(Chunks {
+            bits: *self,
+            width,
+            uint: PhantomData,
+        })
+    }
+
+    fn check(&self, bits: usize) -> Result<(), BitsError> {
+        if bits > self.len() {
+            return Err(BitsError::InsufficientBits {
+                requested: bits,
+                available: self.len(),
+            });
+        }
+        Ok(())
+    }
+
+    fn check_uint<T: UInt>(&self, bits: usize) -> Result<(), BitsError> {
+        if bits > T::BITS {
+            return Err(BitsError::TooWide {
+                requested: bits,
+                max: T::BITS,
+            });
+        }
+        self.check(bits)
+    }
+
+    // Reads up to 56 checked bits from the cached word, first loading the word at the current
+    // byte if the bits are not all in the cached one.
+    fn read_cached(&mut self, bits: usize) -> u64 {
+        debug_assert!(bits <= WORD_BITS);
+        let cached = match self.cache {
+            Some(word)
+                if self.position >= word.index * 8
+                    && self.position + bits <= word.index * 8 + 64 =>
+            {
+                word
+            }
+            _ => {
+                let word = CachedWord {
+                    index: self.position / 8,
+                    bytes: load_bytes(self.inner, self.position / 8),
+                };
+                self.cache = Some(word);
+                word
+            }
+        };
+        O::read_word(cached.bytes, self.position - cached.index * 8, bits)
+    }
+}
+
+impl<'i, O: BitOrder> IntoIterator for Bits<'i, O> {
+    type Item = bool;
+    type IntoIter = Iter<'i, O>;
+
+    fn into_iter(self) -> Iter<'i, O> {
+        Iter { bits: self }
+    }
+}
+
+// Yields each remaining bit of a reader as a `bool`.
+#[derive(Clone, Debug)]
+pub struct Iter<'i, O: BitOrder = Msb0> {
+    bits: Bits<'i, O>,
+}
+
+impl<O: BitOrder> Iterator for Iter<'_, O> {
+    type Item = bool;
+
+    fn next(&mut self) -> Option<bool> {
+        self.bits.take_u8(1).ok().map(|bit| bit == 1)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.bits.len(), Some(self.bits.len()))
+    }
+}
+
+impl<O: BitOrder> ExactSizeIterator for Iter<'_, O> {}
+
+// Yields whole chunks of a fixed number of bits as integers.
+#[derive(Clone, Debug)]
+pub struct Chunks<'i, T, O: BitOrder = Msb0> {
+    bits: Bits<'i, O>,
+    width: usize,
+    uint: PhantomData<T>,
+}
+
+impl<'i, T, O: BitOrder> Chunks<'i, T, O> {
+    // The bits not yet read, which once iteration is over are those too few to make a chunk.
+    pub fn remainder(&self) -> Bits<'i, O> {
+        self.bits
+    }
+}
+
+impl<T: UInt, O: BitOrder> Iterator for Chunks<'_, T, O> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.bits.take_uint(self.width).ok()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let chunks = self.bits.len() / self.width;
+        (chunks, Some(chunks))
+    }
+}
+
+impl<T: UInt, O: BitOrder> ExactSizeIterator for Chunks<'_, T, O> {}
+
+// Reads bits incrementally from an `io::Read`, buffering as much of the source as is needed
+// to satisfy each request.
+#[cfg(feature = "std")]
+#[derive(Debug)]
+pub struct BitReader<R: Read, O: BitOrder = Msb0> {
+    source: R,
+    buffer: Vec<u8>,
+    // Bit position within the buffer, and the number of bits dropped from the front of it.
+    position: usize,
+    discarded: usize,
+    exhausted: bool,
+    order: PhantomData<O>,
+}
+
+// Minimum number of bytes requested from the source at a time.
+#[cfg(feature = "std")]
+const READ_SIZE: usize = 8 * 1024;
+
+#[cfg(feature = "std")]
+impl<R: Read> BitReader<R> {
+    pub fn new(source: R) -> BitReader<R> {
+        BitReader::with_order(source)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<R: Read, O: BitOrder> BitReader<R, O> {
+    pub fn with_order(source: R) -> BitReader<R, O> {
+        BitReader {
+            source,
+            buffer: Vec::new(),
+            position: 0,
+            discarded: 0,
+            exhausted: false,
+            order: PhantomData,
+        }
+    }
+
+    pub fn take(&mut self, bits: usize) -> Result<Vec<u8>, ReadError> {
+        let bytes = self.peek(bits)?;
+        self.position += bits;
+        Ok(bytes)
+    }
+
+    pub fn peek(&mut self, bits: usize) -> Result<Vec<u8>, ReadError> {
+        self.fill(bits)?;
+        Ok(read_vec(bits, |out| {
+            O::read_into(&self.buffer, self.position, bits, out)
+        }))
+    }
+
+    pub fn take_uint<T: UInt>(&mut self, bits: usize) -> Result<T, ReadError> {
+        let value = self.peek_uint(bits)?;
+        self.position += bits;
+        Ok(value)
+    }
+
+    pub fn peek_uint<T: UInt>(&mut self, bits: usize) -> Result<T, ReadError> {
+        if bits > T::BITS {
+            return Err(BitsError::TooWide {
+                requested: bits,
+                max: T::BITS,
+            }
+            .into());
+        }
+        self.fill(bits)?;
+        Ok(T::truncate(O::read_uint(&self.buffer, self.position, bits)))
+    }
+
+    pub fn take_u8(&mut self, bits: usize) -> Result<u8, ReadError> {
+        self.take_uint(bits)
+    }
+
+    pub fn take_u16(&mut self, bits: usize) -> Result<u16, ReadError> {
+        self.take_uint(bits)
+    }
+
+    pub fn take_u32(&mut self, bits: usize) -> Result<u32, ReadError> {
+        self.take_uint(bits)
+    }
+
+    pub fn take_u64(&mut self, bits: usize) -> Result<u64, ReadError> {
+        self.take_uint(bits)
+    }
+
+    pub fn take_u128(&mut self, bits: usize) -> Result<u128, ReadError> {
+        self.take_uint(bits)
+    }
+
+    pub fn skip(&mut self, bits: usize) -> Result<(), ReadError> {
+        self.fill(bits)?;
+        self.position += bits;
+        Ok(())
+    }
+
+    // Number of bits read from the start of the source.
+    pub fn position(&self) -> usize {
+        self.discarded + self.position
+    }
+
+    // Whether the source has no bits left, which may require reading from it.
+    pub fn is_empty(&mut self) -> Result<bool, ReadError> {
+        match self.fill(1) {
+            Ok(()) => Ok(false),
+            Err(ReadError::Bits(BitsError::InsufficientBits { .. })) => Ok(true),
+            Err(error) => Err(error),
+        }
+    }
+
+    pub fn into_inner(self) -> R {
+        self.source
+    }
+
+    // Reads from the source until the buffer holds `bits` bits past the current position.
+    fn fill(&mut self, bits: usize) -> Result<(), ReadError> {
+        let needed = self.position.saturating_add(bits);
+        if needed <= self.buffer.len() * 8 {
+            return Ok(());
+        }
+
+        // Drop the bytes already read before growing the buffer.
+        let consumed = self.position / 8;
+        self.buffer.drain(..consumed);
+        self.position -= consumed * 8;
+        self.discarded += consumed * 8;
+
+        // Reads are capped so an oversized request fails once the source runs out, rather than
+        // allocating the whole request up front.
+        let needed = self.position.saturating_add(bits);
+        while !self.exhausted && self.buffer.len() * 8 < needed {
+            let len = self.buffer.len();
+            let request = (byte_count(needed) - len).clamp(READ_SIZE, 64 * READ_SIZE);
+            self.buffer.resize(len + request, 0);
+            match self.source.read(&mut self.buffer[len..]) {
+                Ok(0) => {
+                    self.buffer.truncate(len);
+                    self.exhausted = true;
+                }
+                Ok(read) => self.buffer.truncate(len + read),
+                Err(error) if error.kind() == io::ErrorKind::Interrupted => {
+                    self.buffer.truncate(len);
+                }
+                Err(error) => {
+                    self.buffer.truncate(len);
+                    return Err(error.into());
+                }
+            }
+        }
+        let available = self.buffer.len() * 8 - self.position;
+        if bits > available {
+            return Err(BitsError::InsufficientBits {
+                requested: bits,
+                available,
+            }
+            .into());
+        }
+        Ok(())
     }
 }
 
-#[derive(Clone, Copy, Debug, Error, PartialEq)]
+// The order of the groups in a continuation-bit varint.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GroupOrder {
+    MostSignificantFirst,
+    LeastSignificantFirst,
+}
+
+// The order in which bits are read from each byte. The first bit read becomes the most
+// significant bit of the value for `Msb0`, and the least significant for `Lsb0`. Values
+// returned as bytes are always big-endian and right-aligned.
+pub trait BitOrder: Copy + Debug + PartialEq {
+    // Reads `bits` bits, at most 128, starting at bit `position` of the slice. The caller
+    // checks that they are available.
+    fn read_uint(slice: &[u8], position: usize, bits: usize) -> u128;
+    // As `read_uint`, for any number of bits, writing them to `out`, which holds exactly as
+    // many bytes as are needed.
+    fn read_into(slice: &[u8], position: usize, bits: usize, out: &mut [u8]);
+    // Reads `bits` bits starting at bit `offset` of eight bytes, where the bits fit in them.
+    fn read_word(bytes: [u8; 8], offset: usize, bits: usize) -> u64;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Msb0;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Lsb0;
+
+impl BitOrder for Msb0 {
+    fn read_uint(slice: &[u8], position: usize, bits: usize) -> u128 {
+        grab_uint(&slice[position / 8..], (position % 8) as u8, bits)
+            .expect("bits checked by the reader.")
+    }
+
+    fn read_into(slice: &[u8], position: usize, bits: usize, out: &mut [u8]) {
+        grab_bits_into(&slice[position / 8..], (position % 8) as u8, bits, out)
+            .expect("bits checked by the reader.");
+    }
+
+    fn read_word(bytes: [u8; 8], offset: usize, bits: usize) -> u64 {
+        debug_assert!(offset + bits <= 64);
+        if bits == 0 {
+            return 0;
+        }
+        (u64::from_be_bytes(bytes) << offset) >> (64 - bits)
+    }
+}
+
+impl BitOrder for Lsb0 {
+    fn read_uint(slice: &[u8], mut position: usize, mut bits: usize) -> u128 {
+        debug_assert!(bits <= 128);
+        let mut value = 0u128;
+        let mut shift = 0;
+        while bits > 0 {
+            let offset = position % 8;
+            let chunk_size = core::cmp::min(8 - offset, bits);
+            let chunk = (slice[position / 8] >> offset) & (0xFF >> (8 - chunk_size));
+            value |= (chunk as u128) << shift;
+            shift += chunk_size;
+            position += chunk_size;
+            bits -= chunk_size;
+        }
+        value
+    }
+
+    // The first 8 bits read form the least significant byte, and so on.
+    fn read_into(slice: &[u8], position: usize, bits: usize, out: &mut [u8]) {
+        debug_assert_eq!(out.len(), byte_count(bits));
+        for (byte, start) in out.iter_mut().rev().zip((0..bits).step_by(8)) {
+            let chunk_size = core::cmp::min(8, bits - start);
+            *byte = Lsb0::read_uint(slice, position + start, chunk_size) as u8;
+        }
+    }
+
+    fn read_word(bytes: [u8; 8], offset: usize, bits: usize) -> u64 {
+        debug_assert!(offset + bits <= 64);
+        if bits == 0 {
+            return 0;
+        }
+        (u64::from_le_bytes(bytes) >> offset) & (u64::MAX >> (64 - bits))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum BitsError {
-    #[error("{requested} bits were requested, but only {available} bits are available.")]
     InsufficientBits { requested: usize, available: usize },
+    TooWide { requested: usize, max: usize },
+    OutOfBounds { position: usize, len: usize },
+    BufferTooSmall { needed: usize, len: usize },
+    Overflow { max: usize },
+    InvalidHex { index: usize },
+}
+
+// Implemented by hand, as `thiserror` requires std.
+impl fmt::Display for BitsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BitsError::InsufficientBits {
+                requested,
+                available,
+            } => write!(
+                f,
+                "{requested} bits were requested, but only {available} bits are available."
+            ),
+            BitsError::TooWide { requested, max } => write!(
+                f,
+                "{requested} bits were requested, but the integer type only holds {max} bits."
+            ),
+            BitsError::OutOfBounds { position, len } => write!(
+                f,
+                "Position {position} is beyond the end of the {len} bits available."
+            ),
+            BitsError::BufferTooSmall { needed, len } => write!(
+                f,
+                "{needed} bytes are needed, but the buffer only holds {len} bytes."
+            ),
+            BitsError::Overflow { max } => {
+                write!(f, "The value read does not fit in {max} bits.")
+            }
+            BitsError::InvalidHex { index } => {
+                write!(f, "The character at index {index} is not a hex digit.")
+            }
+        }
+    }
+}
+
+impl core::error::Error for BitsError {}
+
+#[cfg(feature = "std")]
+#[derive(Debug)]
+pub enum ReadError {
+    Bits(BitsError),
+    Io(io::Error),
+}
+
+#[cfg(feature = "std")]
+impl fmt::Display for ReadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReadError::Bits(error) => fmt::Display::fmt(error, f),
+            ReadError::Io(_) => write!(f, "Failed to read from the source."),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ReadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ReadError::Bits(_) => None,
+            ReadError::Io(error) => Some(error),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<BitsError> for ReadError {
+    fn from(error: BitsError) -> ReadError {
+        ReadError::Bits(error)
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<io::Error> for ReadError {
+    fn from(error: io::Error) -> ReadError {
+        ReadError::Io(error)
+    }
+}
+
+// Unsigned integer types that can be read directly from `Bits`.
+pub trait UInt: Sized {
+    const BITS: usize;
+    fn truncate(value: u128) -> Self;
+    fn widen(self) -> u128;
+}
+
+macro_rules! impl_uint {
+    ($($ty:ty),*) => {
+        $(
+            impl UInt for $ty {
+                const BITS: usize = <$ty>::BITS as usize;
+                fn truncate(value: u128) -> Self {
+                    value as $ty
+                }
+                fn widen(self) -> u128 {
+                    self as u128
+                }
+            }
+        )*
+    };
+}
+
+impl_uint!(u8, u16, u32, u64, u128);
+
+// Builds a bit stream MSB-first, in the same layout that `Bits` reads.
+#[cfg(feature = "alloc")]
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BitWriter {
+    bytes: Vec<u8>,
+    // Number of bits used in the last byte, or 0 if it is full.
+    offset: u8,
+}
+
+#[cfg(feature = "alloc")]
+impl BitWriter {
+    pub fn new() -> BitWriter {
+        BitWriter::default()
+    }
+
+    // Appends the low `bits` bits of `value`; any higher bits are ignored.
+    pub fn write_uint<T: UInt>(&mut self, value: T, bits: usize) -> Result<(), BitsError> {
+        if bits > T::BITS {
+            return Err(BitsError::TooWide {
+                requested: bits,
+                max: T::BITS,
+            });
+        }
+        self.push(value.widen(), bits);
+        Ok(())
+    }
+
+    pub fn write_bit(&mut self, bit: bool) {
+        self.push(bit as u128, 1);
+    }
+
+    // Appends `bits` bits given in the layout returned by `Bits::take`: big-endian and
+    // right-aligned, so the first byte holds any bits that do not fill a whole byte.
+    pub fn write(&mut self, bytes: &[u8], bits: usize) {
+        assert_eq!(
+            bytes.len(),
+            byte_count(bits),
+            "{bits} bits must be given as {} bytes.",
+            byte_count(bits)
+        );
+        let mut chunk_size = match bits % 8 {
+            0 => 8,
+            unaligned => unaligned,
+        };
+        for &byte in bytes {
+            self.push(byte as u128, chunk_size);
+            chunk_size = 8;
+        }
+    }
+
+    // Appends the bits written to another writer, excluding its padding.
+    pub fn append(&mut self, other: &BitWriter) {
+        let (full, last) = match other.offset {
+            0 => (&other.bytes[..], None),
+            offset => (
+                &other.bytes[..other.bytes.len() - 1],
+                other
+                    .bytes
+                    .last()
+                    .map(|&byte| (byte >> (8 - offset), offset)),
+            ),
+        };
+        for &byte in full {
+            self.push(byte as u128, 8);
+        }
+        if let Some((byte, bits)) = last {
+            self.push(byte as u128, bits as usize);
+        }
+    }
+
+    // Appends zeros up to the next byte boundary.
+    pub fn pad_to_byte(&mut self) {
+        // Bytes are pushed zeroed, so only the offset needs resetting.
+        self.offset = 0;
+    }
+
+    // Length in bits.
+    pub fn len(&self) -> usize {
+        match self.offset {
+            0 => self.bytes.len() * 8,
+            offset => (self.bytes.len() - 1) * 8 + offset as usize,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    // Returns the bytes written, with the last byte padded with zeros.
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+
+    fn push(&mut self, value: u128, mut bits: usize) {
+        while bits > 0 {
+            if self.offset == 0 {
+                self.bytes.push(0);
+            }
+            let free = 8 - self.offset as usize;
+            let chunk_size = core::cmp::min(free, bits);
+            let chunk = (value >> (bits - chunk_size)) as u8 & (0xFF >> (8 - chunk_size));
+            *self.bytes.last_mut().expect("byte pushed.") |= chunk << (free - chunk_size);
+            self.offset = ((self.offset as usize + chunk_size) % 8) as u8;
+            bits -= chunk_size;
+        }
+    }
+}
+
+// Collects `bits` bits written by `read` into a vector. Reads that fit go through a buffer on
+// the stack, since `vec![0; len]` asks the allocator for zeroed memory, which costs more than
+// the read itself for the few bytes usually taken.
+#[cfg(feature = "alloc")]
+fn read_vec(bits: usize, read: impl FnOnce(&mut [u8])) -> Vec<u8> {
+    let len = byte_count(bits);
+    if len <= 16 {
+        let mut buffer = [0; 16];
+        read(&mut buffer[..len]);
+        buffer[..len].to_vec()
+    } else {
+        let mut bytes = vec![0; len];
+        read(&mut bytes);
+        bytes
+    }
 }
 
 fn byte_count(bits: usize) -> usize {
     let bytes = bits / 8;
-    if bits % 8 > 0 {
+    if !bits.is_multiple_of(8) {
         bytes + 1
     } else {
         bytes
     }
 }
 
+// Number of bits in the slice after skipping `offset` bits of the first byte.
+fn available_bits(slice: &[u8], offset: u8) -> usize {
+    debug_assert!(offset < 8);
+    (slice.len() * 8).saturating_sub(offset as usize)
+}
+
 fn valid_request_size(slice: &[u8], offset: u8, request: usize) -> bool {
+    request <= available_bits(slice, offset)
+}
+
+fn insufficient_bits(slice: &[u8], offset: u8, request: usize) -> BitsError {
+    BitsError::InsufficientBits {
+        requested: request,
+        available: available_bits(slice, offset),
+    }
+}
+
+// Loads the 8 bytes starting at `index`, padding with zeros past the end of the slice.
+fn load_bytes(slice: &[u8], index: usize) -> [u8; 8] {
+    // A fixed-size copy compiles to a single load, where the padded one calls `memcpy`.
+    if let Some(bytes) = slice.get(index..index + 8) {
+        return <[u8; 8]>::try_from(bytes).unwrap();
+    }
+    let mut bytes = [0; 8];
+    if index < slice.len() {
+        let len = core::cmp::min(8, slice.len() - index);
+        bytes[..len].copy_from_slice(&slice[index..index + len]);
+    }
+    bytes
+}
+
+// Reads up to 56 bits starting `position` bits into the slice. Since the offset within the
+// first byte is at most 7, a single word always holds them.
+fn read_word(slice: &[u8], position: usize, bits: usize) -> u64 {
+    debug_assert!(bits <= WORD_BITS);
+    Msb0::read_word(load_bytes(slice, position / 8), position % 8, bits)
+}
+
+const WORD_BITS: usize = 56;
+
+// Like `grab_bits`, but accumulates the bits into an integer.
+fn grab_uint(slice: &[u8], offset: u8, bits: usize) -> Result<u128, BitsError> {
     debug_assert!(offset < 8);
-    let req_byte_count = byte_count(request);
-    let slice_overflow_bit_alignment = 8 - offset as usize;
-    let req_overflow_bit_alignment = (request.wrapping_sub(1) % 8) + 1;
-    req_byte_count < slice.len()
-        || (req_byte_count == slice.len()
-            && req_overflow_bit_alignment <= slice_overflow_bit_alignment)
-}
-
-fn grab_bits(
-    mut slice: &[u8],
-    mut offset: u8,
-    mut bits: usize,
-) -> Result<(&[u8], u8, Vec<u8>), BitsError> {
+    debug_assert!(bits <= 128);
+    if !valid_request_size(slice, offset, bits) {
+        return Err(insufficient_bits(slice, offset, bits));
+    }
+
+    let mut value = 0u128;
+    let mut position = offset as usize;
+    let mut remaining = bits;
+    while remaining > 0 {
+        let chunk_size = core::cmp::min(WORD_BITS, remaining);
+        value = value << chunk_size | read_word(slice, position, chunk_size) as u128;
+        position += chunk_size;
+        remaining -= chunk_size;
+    }
+    Ok(value)
+}
+
+// Writes the bits to `out` in big-endian order, right-aligned so that the first byte holds any
+// bits that do not fill a whole byte. `out` must hold exactly as many bytes as are needed.
+// Bits are read a word at a time, so that the remaining bytes are produced 7 at once.
+fn grab_bits_into(slice: &[u8], offset: u8, bits: usize, out: &mut [u8]) -> Result<(), BitsError> {
     debug_assert!(offset < 8);
-    // Simple case
-    if bits == 0 {
-        return Ok((slice, offset, vec![]));
+    debug_assert_eq!(out.len(), byte_count(bits));
+    if !valid_request_size(slice, offset, bits) {
+        return Err(insufficient_bits(slice, offset, bits));
     }
 
+    let mut position = offset as usize;
+    let mut written = 0;
+    let unaligned_bits = bits % 8;
+    if unaligned_bits > 0 {
+        out[0] = read_word(slice, position, unaligned_bits) as u8;
+        position += unaligned_bits;
+        written += 1;
+    }
+    let mut remaining = bits - unaligned_bits;
+    while remaining > 0 {
+        let chunk_size = core::cmp::min(WORD_BITS, remaining);
+        let word = read_word(slice, position, chunk_size).to_be_bytes();
+        let chunk_bytes = chunk_size / 8;
+        out[written..written + chunk_bytes].copy_from_slice(&word[8 - chunk_bytes..]);
+        written += chunk_bytes;
+        position += chunk_size;
+        remaining -= chunk_size;
+    }
+    Ok(())
+}
+
+// Allocating form of `grab_bits_into`.
+#[cfg(all(feature = "alloc", any(test, feature = "bench")))]
+fn grab_bits(slice: &[u8], offset: u8, bits: usize) -> Result<Vec<u8>, BitsError> {
     if !valid_request_size(slice, offset, bits) {
-        return Err(BitsError::InsufficientBits {
-            requested: bits,
-            available: slice.len() * 8 + (offset.wrapping_sub(1) % 8) as usize - 7,
-        });
+        return Err(insufficient_bits(slice, offset, bits));
     }
+    Ok(read_vec(bits, |out| {
+        grab_bits_into(slice, offset, bits, out).expect("bits checked above.")
+    }))
+}
 
-    // Pull chunks from the slice and add them to the container of bytes we are returning.
-    // The size of the chunks varies depending on how the alignment of the request and the
-    // slice compare.
-    let mut bytes = Vec::with_capacity(byte_count(bits));
-    let mut unaligned_bits = (bits % 8) as u8;
-    while bits > 0 {
-        if unaligned_bits > 0 {
-            // We work with the smaller of the unaligned request bits, or the remaining bits
-            // in the first byte of the slice.
-            let chunk_size = std::cmp::min(8 - offset, unaligned_bits);
-            let byte = (slice[0] << offset) >> (8 - chunk_size);
-            offset += chunk_size;
-            bits -= chunk_size as usize;
-            unaligned_bits -= chunk_size;
-            if offset >= 8 {
-                slice = &slice[1..];
-                offset -= 8;
-            }
-            match bytes.pop() {
-                Some(value) => bytes.push((value << chunk_size) | byte),
-                None => bytes.push(byte),
-            }
-        } else {
-            let chunk_size = std::cmp::min(8 - offset as usize, bits) as u8;
+// The original byte-at-a-time implementations, kept as references for the word-at-a-time ones
+// in the tests and the benchmark.
+#[cfg(all(feature = "alloc", any(test, feature = "bench")))]
+mod bytewise {
+    use super::{byte_count, insufficient_bits, valid_request_size, BitsError};
+    use alloc::{vec, vec::Vec};
+
+    pub fn grab_uint(mut slice: &[u8], mut offset: u8, mut bits: usize) -> Result<u128, BitsError> {
+        debug_assert!(offset < 8);
+        debug_assert!(bits <= 128);
+        if !valid_request_size(slice, offset, bits) {
+            return Err(insufficient_bits(slice, offset, bits));
+        }
+
+        let mut value = 0u128;
+        while bits > 0 {
+            let chunk_size = core::cmp::min(8 - offset as usize, bits) as u8;
             let byte = (slice[0] << offset) >> (8 - chunk_size);
+            value = value << chunk_size | byte as u128;
             offset += chunk_size;
             bits -= chunk_size as usize;
             if offset >= 8 {
                 slice = &slice[1..];
                 offset -= 8;
             }
-            if chunk_size < 8 && offset == 0 {
-                unaligned_bits = 8 - chunk_size;
+        }
+        Ok(value)
+    }
+
+    pub fn grab_bits(
+        mut slice: &[u8],
+        mut offset: u8,
+        mut bits: usize,
+    ) -> Result<Vec<u8>, BitsError> {
+        debug_assert!(offset < 8);
+        // Simple case
+        if bits == 0 {
+            return Ok(vec![]);
+        }
+
+        if !valid_request_size(slice, offset, bits) {
+            return Err(insufficient_bits(slice, offset, bits));
+        }
+
+        // Pull chunks from the slice and add them to the container of bytes we are returning.
+        // The size of the chunks varies depending on how the alignment of the request and the
+        // slice compare.
+        let mut bytes = Vec::with_capacity(byte_count(bits));
+        let mut unaligned_bits = (bits % 8) as u8;
+        while bits > 0 {
+            if unaligned_bits > 0 {
+                // We work with the smaller of the unaligned request bits, or the remaining bits
+                // in the first byte of the slice.
+                let chunk_size = core::cmp::min(8 - offset, unaligned_bits);
+                let byte = (slice[0] << offset) >> (8 - chunk_size);
+                offset += chunk_size;
+                bits -= chunk_size as usize;
+                unaligned_bits -= chunk_size;
+                if offset >= 8 {
+                    slice = &slice[1..];
+                    offset -= 8;
+                }
+                match bytes.pop() {
+                    Some(value) => bytes.push((value << chunk_size) | byte),
+                    None => bytes.push(byte),
+                }
+            } else {
+                let chunk_size = core::cmp::min(8 - offset as usize, bits) as u8;
+                let byte = (slice[0] << offset) >> (8 - chunk_size);
+                offset += chunk_size;
+                bits -= chunk_size as usize;
+                if offset >= 8 {
+                    slice = &slice[1..];
+                    offset -= 8;
+                }
+                if chunk_size < 8 && offset == 0 {
+                    unaligned_bits = 8 - chunk_size;
+                }
+                bytes.push(byte);
             }
-            bytes.push(byte);
         }
+        Ok(bytes)
     }
-    Ok((slice, offset, bytes))
 }
 
+// The raw reads, word-at-a-time and byte-at-a-time, for benches/bits.rs to compare. Each reads
+// `bits` bits starting `offset` bits into the slice.
+#[cfg(feature = "bench")]
+#[doc(hidden)]
+pub mod bench {
+    pub use super::bytewise::{grab_bits as grab_bits_bytewise, grab_uint as grab_uint_bytewise};
+    use super::BitsError;
+    use alloc::vec::Vec;
+
+    pub fn grab_uint(slice: &[u8], offset: u8, bits: usize) -> Result<u128, BitsError> {
+        super::grab_uint(slice, offset, bits)
+    }
+
+    pub fn grab_bits(slice: &[u8], offset: u8, bits: usize) -> Result<Vec<u8>, BitsError> {
+        super::grab_bits(slice, offset, bits)
+    }
+}
+
+// Tests that need an allocator or `io` are gated on the features that provide them, so the
+// rest also run in `alloc` and `core` only builds.
 #[cfg(test)]
 mod tests {
     use super::*;
+    #[cfg(feature = "std")]
+    use proptest::prelude::*;
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_grab_against_bytewise() {
+        let bytes: Vec<u8> = (0..24u8).map(|i| i.wrapping_mul(73) ^ 0xC3).collect();
+        for len in 0..=bytes.len() {
+            let slice = &bytes[..len];
+            for offset in 0..8 {
+                for width in 0..=128 {
+                    assert_eq!(
+                        grab_bits(slice, offset, width),
+                        bytewise::grab_bits(slice, offset, width),
+                        "grab_bits with {len} bytes, offset {offset} and width {width}"
+                    );
+                    assert_eq!(
+                        grab_uint(slice, offset, width),
+                        bytewise::grab_uint(slice, offset, width),
+                        "grab_uint with {len} bytes, offset {offset} and width {width}"
+                    );
+                }
+            }
+        }
+    }
 
     #[test]
     fn test_byte_count() {
@@ -152,6 +1176,30 @@ mod tests {
         assert!(!valid_request_size(&slice, 0, 65));
     }
 
+    #[test]
+    fn test_insufficient_bits() {
+        let slice = [1, 2];
+        assert_eq!(available_bits(&slice, 0), 16);
+        assert_eq!(available_bits(&slice[1..], 7), 1);
+        assert_eq!(available_bits(&slice[2..], 0), 0);
+        assert_eq!(available_bits(&slice[2..], 3), 0);
+        assert_eq!(
+            grab_bits_into(&slice, 3, 14, &mut [0; 2]),
+            Err(BitsError::InsufficientBits {
+                requested: 14,
+                available: 13
+            })
+        );
+        assert_eq!(
+            grab_uint(&slice[1..], 0, 9),
+            Err(BitsError::InsufficientBits {
+                requested: 9,
+                available: 8
+            })
+        );
+    }
+
+    #[cfg(feature = "alloc")]
     #[test]
     fn basic_test() {
         let mut bits = Bits::new(&[0b00001111, 0b11000011]);
@@ -170,4 +1218,549 @@ mod tests {
         assert_eq!(bits.take(5), Ok(vec![0b00011000]));
         assert_eq!(bits.take(3), Ok(vec![0b00000011]))
     }
+
+    #[test]
+    fn test_take_into() {
+        let mut bits = Bits::new(&[0b00001111, 0b11000011, 0b10101010]);
+        let mut buffer = [0; 4];
+        assert_eq!(bits.take_into(4, &mut buffer), Ok(&[0b0][..]));
+        assert_eq!(
+            bits.peek_into(12, &mut buffer),
+            Ok(&[0b1111, 0b11000011][..])
+        );
+        assert_eq!(
+            bits.take_into(20, &mut buffer),
+            Ok(&[0b1111, 0b11000011, 0b10101010][..])
+        );
+        assert_eq!(
+            bits.peek_into(1, &mut buffer),
+            Err(BitsError::InsufficientBits {
+                requested: 1,
+                available: 0
+            })
+        );
+        let mut bits = Bits::new(&[0xFF; 4]);
+        assert_eq!(
+            bits.take_into(17, &mut buffer[..2]),
+            Err(BitsError::BufferTooSmall { needed: 3, len: 2 })
+        );
+        assert_eq!(bits.position(), 0);
+    }
+
+    #[test]
+    fn test_take_varint() {
+        // The BITS literal 2021 from day 16, after its header.
+        let mut bits = Bits::new(&[0b1011_1111, 0b1000_1010]);
+        assert_eq!(
+            bits.take_varint::<u16>(4, GroupOrder::MostSignificantFirst),
+            Ok(2021)
+        );
+        assert_eq!(bits.position(), 15);
+        let mut bits = Bits::new(&[0b1011_1111, 0b1000_1010]);
+        assert_eq!(
+            bits.take_varint::<u16>(4, GroupOrder::LeastSignificantFirst),
+            Ok(0b0101_1110_0111)
+        );
+        assert_eq!(
+            Bits::new(&[0b1011_1111, 0b1000_1010])
+                .take_varint::<u8>(4, GroupOrder::MostSignificantFirst),
+            Err(BitsError::Overflow { max: 8 })
+        );
+        // Leading zero groups never overflow.
+        let mut bits = Bits::new(&[0b1000_0100, 0b0000_0010]);
+        assert_eq!(
+            bits.take_varint::<u8>(4, GroupOrder::MostSignificantFirst),
+            Ok(1)
+        );
+        // Nor do groups wider than the type, as long as the value fits.
+        let mut bits = Bits::new(&[0x80, 0x00, 0x10]);
+        assert_eq!(
+            bits.take_varint::<u8>(9, GroupOrder::MostSignificantFirst),
+            Ok(1)
+        );
+        assert_eq!(
+            Bits::new(&[0x3F, 0xC0]).take_varint::<u8>(9, GroupOrder::MostSignificantFirst),
+            Ok(255)
+        );
+        assert_eq!(
+            Bits::new(&[0x40, 0x00]).take_varint::<u8>(9, GroupOrder::MostSignificantFirst),
+            Err(BitsError::Overflow { max: 8 })
+        );
+
+        let mut bits = Bits::new(&[0b1111_1111]);
+        assert!(matches!(
+            bits.take_varint::<u64>(4, GroupOrder::MostSignificantFirst),
+            Err(BitsError::InsufficientBits { .. })
+        ));
+        assert_eq!(bits.position(), 0);
+    }
+
+    #[test]
+    fn test_take_leb128() {
+        let mut bits = Bits::new(&[0xE5, 0x8E, 0x26, 0x00, 0x80, 0x01]);
+        assert_eq!(bits.take_leb128::<u32>(), Ok(624485));
+        assert_eq!(bits.take_leb128::<u8>(), Ok(0));
+        assert_eq!(bits.take_leb128::<u8>(), Ok(128));
+        assert!(bits.is_empty());
+        let bytes = [0xFF, 0xFF, 0x07];
+        assert_eq!(
+            Bits::new(&bytes).take_leb128::<u16>(),
+            Err(BitsError::Overflow { max: 16 })
+        );
+        assert_eq!(Bits::new(&bytes).take_leb128::<u32>(), Ok(0x1FFFF));
+        let mut bits = Bits::<Lsb0>::with_order(&[0x80, 0x01]);
+        assert_eq!(bits.take_leb128::<u16>(), Ok(128));
+    }
+
+    #[test]
+    fn test_take_elias_gamma() {
+        // 1, 2, 5 and 8 as "1", "010", "00101" and "0001000".
+        let mut bits = Bits::new(&[0b1010_0010, 0b1000_1000]);
+        assert_eq!(bits.take_elias_gamma::<u8>(), Ok(1));
+        assert_eq!(bits.take_elias_gamma::<u8>(), Ok(2));
+        assert_eq!(bits.take_elias_gamma::<u8>(), Ok(5));
+        assert_eq!(bits.take_elias_gamma::<u8>(), Ok(8));
+        assert!(bits.is_empty());
+        let mut bits = Bits::new(&[0, 0b1000_0000, 0]);
+        assert_eq!(
+            bits.take_elias_gamma::<u8>(),
+            Err(BitsError::Overflow { max: 8 })
+        );
+        assert_eq!(bits.take_elias_gamma::<u16>(), Ok(1 << 8));
+        assert!(matches!(
+            Bits::new(&[0]).take_elias_gamma::<u8>(),
+            Err(BitsError::InsufficientBits { .. })
+        ));
+    }
+
+    #[test]
+    fn test_from_hex() {
+        let mut buffer = [0xFF; 4];
+        let mut bits = Bits::from_hex("D2fE28", &mut buffer).unwrap();
+        assert_eq!(bits.len(), 24);
+        let mut out = [0; 3];
+        assert_eq!(bits.take_into(24, &mut out), Ok(&[0xD2, 0xFE, 0x28][..]));
+        let mut bits = Bits::from_hex("A5B", &mut buffer).unwrap();
+        assert_eq!(bits.len(), 12);
+        assert_eq!(bits.take_u16(12), Ok(0xA5B));
+        assert_eq!(
+            Bits::from_hex("D2G", &mut buffer),
+            Err(BitsError::InvalidHex { index: 2 })
+        );
+        assert_eq!(
+            Bits::from_hex("D2FE2800AB", &mut buffer),
+            Err(BitsError::BufferTooSmall { needed: 5, len: 4 })
+        );
+        assert!(Bits::from_hex("", &mut []).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_iterators() {
+        let bytes = [0b1010_0011, 0b1100_0000];
+        let mut bits = Bits::new(&bytes);
+        bits.skip(2).unwrap();
+        let bools: Vec<_> = bits.iter().take(6).collect();
+        assert_eq!(bools, [true, false, false, false, true, true]);
+        assert_eq!(bits.position(), 2);
+        assert_eq!(bits.into_iter().len(), 14);
+        assert_eq!(bits.into_iter().filter(|&bit| bit).count(), 5);
+
+        let mut chunks = Bits::new(&bytes).chunks::<u8>(3).unwrap();
+        assert_eq!(chunks.len(), 5);
+        let values: Vec<_> = chunks.by_ref().collect();
+        assert_eq!(values, [0b101, 0b000, 0b111, 0b100, 0b000]);
+        assert_eq!(chunks.remainder().len(), 1);
+        assert_eq!(chunks.remainder().position(), 15);
+
+        let lsb = Bits::<Lsb0>::with_order(&bytes);
+        let nibbles: Vec<u8> = lsb.chunks(4).unwrap().collect();
+        assert_eq!(nibbles, [0b0011, 0b1010, 0b0000, 0b1100]);
+        assert_eq!(
+            Bits::new(&bytes).chunks::<u8>(9).err(),
+            Some(BitsError::TooWide {
+                requested: 9,
+                max: 8
+            })
+        );
+        assert!(Bits::new(&bytes).chunks::<u8>(0).is_err());
+    }
+
+    #[test]
+    fn test_take_uint() {
+        let bytes = [0b00001111, 0b11000011, 0b10101010, 0b01010101, 0b11111111];
+        let mut bits = Bits::new(&bytes);
+        assert_eq!(bits.take_u8(4), Ok(0b0));
+        assert_eq!(bits.peek_uint::<u16>(12), Ok(0b1111_1100_0011));
+        assert_eq!(bits.take_u16(12), Ok(0b1111_1100_0011));
+        assert_eq!(bits.take_u32(17), Ok(0b1_0101_0100_1010_1011));
+        assert_eq!(
+            bits.take_u8(9),
+            Err(BitsError::TooWide {
+                requested: 9,
+                max: 8
+            })
+        );
+        assert_eq!(
+            bits.take_u64(8),
+            Err(BitsError::InsufficientBits {
+                requested: 8,
+                available: 7
+            })
+        );
+        assert_eq!(bits.take_u8(0), Ok(0));
+        assert_eq!(bits.take_u128(7), Ok(0b1111111));
+        assert!(bits.is_empty());
+
+        // Typed reads agree with the byte-oriented ones.
+        let bytes: Vec<u8> = (0..=255).collect();
+        let mut buffer = [0; 16];
+        for offset in 0..8 {
+            for width in 0..=128 {
+                let mut bits = Bits::new(&bytes);
+                bits.skip(offset).unwrap();
+                let peeked = bits.peek_into(width, &mut buffer).unwrap();
+                let expected = peeked.iter().fold(0u128, |acc, &byte| {
+                    acc.checked_shl(8).unwrap_or(0) | byte as u128
+                });
+                assert_eq!(bits.peek_uint::<u128>(width), Ok(expected));
+            }
+        }
+    }
+
+    #[test]
+    fn test_position() {
+        let bytes = [0b00001111, 0b11000011, 0b10101010];
+        let mut bits = Bits::new(&bytes);
+        assert_eq!((bits.position(), bits.len()), (0, 24));
+        bits.skip(5).unwrap();
+        assert_eq!((bits.position(), bits.len()), (5, 19));
+        let mut sub_reader = bits.split(10).unwrap();
+        assert_eq!((bits.position(), bits.len()), (15, 9));
+        assert_eq!((sub_reader.position(), sub_reader.len()), (0, 10));
+        assert_eq!(sub_reader.take_u16(10), Ok(0b1111100001));
+        assert!(sub_reader.is_empty());
+        assert_eq!(
+            sub_reader.take_u8(1),
+            Err(BitsError::InsufficientBits {
+                requested: 1,
+                available: 0
+            })
+        );
+        sub_reader.seek(3).unwrap();
+        assert_eq!(sub_reader.take_u8(3), Ok(0b110));
+        assert_eq!(
+            sub_reader.seek(11),
+            Err(BitsError::OutOfBounds {
+                position: 11,
+                len: 10
+            })
+        );
+        assert_eq!(
+            bits.split(10).map(|_| ()),
+            Err(BitsError::InsufficientBits {
+                requested: 10,
+                available: 9
+            })
+        );
+        assert_eq!(
+            bits.skip(10),
+            Err(BitsError::InsufficientBits {
+                requested: 10,
+                available: 9
+            })
+        );
+        bits.seek(0).unwrap();
+        assert_eq!(bits.take_u8(8), Ok(0b00001111));
+        bits.seek(24).unwrap();
+        assert!(bits.is_empty());
+    }
+
+    // Checks reads against a model where `bit(byte, index)` gives the index'th bit read from a
+    // byte, and `value(bits)` assembles the bits read into an integer.
+    fn check_order<O: BitOrder>(bit: fn(u8, usize) -> bool, value: fn(&[bool]) -> u128) {
+        let bytes: Vec<u8> = (0..40u8).map(|i| i.wrapping_mul(97) ^ 0x5A).collect();
+        let model: Vec<bool> = bytes
+            .iter()
+            .flat_map(|&byte| (0..8).map(move |index| bit(byte, index)))
+            .collect();
+        let mut buffer = [0; 16];
+        for start in 0..16 {
+            for width in 0..=128 {
+                let mut bits = Bits::<O>::with_order(&bytes);
+                bits.skip(start).unwrap();
+                let expected = value(&model[start..start + width]);
+                assert_eq!(bits.peek_uint::<u128>(width), Ok(expected));
+                let expected_bytes = &expected.to_be_bytes()[16 - byte_count(width)..];
+                assert_eq!(bits.take_into(width, &mut buffer), Ok(expected_bytes));
+                assert_eq!(bits.position(), start + width);
+            }
+        }
+        // Successive reads share a cached word, which must be reloaded once they run past it or
+        // seek back before it.
+        for width in 1..=WORD_BITS {
+            let mut bits = Bits::<O>::with_order(&bytes);
+            for start in (0..model.len() - width).step_by(width) {
+                let expected = value(&model[start..start + width]) as u64;
+                assert_eq!(bits.take_u64(width), Ok(expected));
+            }
+            bits.seek(width / 2).unwrap();
+            let expected = value(&model[width / 2..width / 2 + width]) as u64;
+            assert_eq!(bits.take_u64(width), Ok(expected));
+        }
+        let mut bits = Bits::<O>::with_order(&bytes[..1]);
+        let first = model[0] as u8;
+        assert_eq!(bits.take_u8(1), Ok(first));
+        let mut sub_reader = bits.split(4).unwrap();
+        assert_eq!(sub_reader.take_u8(4), Ok(value(&model[1..5]) as u8));
+        assert_eq!(
+            bits.take_u8(4),
+            Err(BitsError::InsufficientBits {
+                requested: 4,
+                available: 3
+            })
+        );
+    }
+
+    #[test]
+    fn test_bit_orders() {
+        check_order::<Msb0>(
+            |byte, index| (byte >> (7 - index)) & 1 == 1,
+            |bits| bits.iter().fold(0, |acc, &bit| acc << 1 | bit as u128),
+        );
+        check_order::<Lsb0>(
+            |byte, index| (byte >> index) & 1 == 1,
+            |bits| {
+                bits.iter()
+                    .rev()
+                    .fold(0, |acc, &bit| acc << 1 | bit as u128)
+            },
+        );
+
+        // DEFLATE stores a block header as a final bit then a 2 bit type, LSB-first.
+        let mut bits = Bits::<Lsb0>::with_order(&[0b0000_0101]);
+        assert_eq!(bits.take_u8(1), Ok(1));
+        assert_eq!(bits.take_u8(2), Ok(0b10));
+    }
+
+    // Yields at most `chunk` bytes per read, to exercise the buffering.
+    #[cfg(feature = "std")]
+    struct Trickle<'a> {
+        bytes: &'a [u8],
+        chunk: usize,
+    }
+
+    #[cfg(feature = "std")]
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let len = self.chunk.min(buf.len()).min(self.bytes.len());
+            buf[..len].copy_from_slice(&self.bytes[..len]);
+            self.bytes = &self.bytes[len..];
+            Ok(len)
+        }
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_bit_reader() {
+        let bytes: Vec<u8> = (0..20_000u32).map(|i| (i * 31 % 251) as u8).collect();
+        let widths = [3, 0, 17, 64, 1, 128, 9, 200, 8, 5];
+        let mut expected = Bits::new(&bytes);
+        let mut reader = BitReader::new(Trickle {
+            bytes: &bytes,
+            chunk: 7,
+        });
+        for &width in widths.iter().cycle().take(2_000) {
+            assert_eq!(reader.peek(width).unwrap(), expected.peek(width).unwrap());
+            if width <= 128 {
+                assert_eq!(
+                    reader.take_uint::<u128>(width).unwrap(),
+                    expected.take_uint::<u128>(width).unwrap()
+                );
+            } else {
+                assert_eq!(reader.take(width).unwrap(), expected.take(width).unwrap());
+            }
+            assert_eq!(reader.position(), expected.position());
+        }
+        let remaining = expected.len();
+        assert!(matches!(
+            reader.take(remaining + 1),
+            Err(ReadError::Bits(BitsError::InsufficientBits { available, .. }))
+                if available == remaining
+        ));
+        reader.skip(remaining - 3).unwrap();
+        expected.skip(remaining - 3).unwrap();
+        assert_eq!(reader.take_u8(3).unwrap(), expected.take_u8(3).unwrap());
+        assert!(reader.is_empty().unwrap());
+        assert!(matches!(
+            reader.take(usize::MAX),
+            Err(ReadError::Bits(BitsError::InsufficientBits {
+                available: 0,
+                ..
+            }))
+        ));
+
+        let mut reader = BitReader::<_, Lsb0>::with_order(io::Cursor::new([0b0000_0101]));
+        assert_eq!(reader.take_u8(1).unwrap(), 1);
+        assert_eq!(reader.take_u8(2).unwrap(), 0b10);
+        assert!(!reader.is_empty().unwrap());
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_bit_writer() {
+        let mut writer = BitWriter::new();
+        assert!(writer.is_empty());
+        writer.write_uint(0b101u8, 3).unwrap();
+        writer.write_bit(true);
+        writer.write(&[0b11, 0b00001111], 10);
+        assert_eq!(writer.len(), 14);
+        writer.pad_to_byte();
+        assert_eq!(writer.len(), 16);
+        writer.write_uint(0xABCDu16, 12).unwrap();
+        assert_eq!(
+            writer.write_uint(0u8, 9),
+            Err(BitsError::TooWide {
+                requested: 9,
+                max: 8
+            })
+        );
+        let mut other = BitWriter::new();
+        other.write_uint(0b11u8, 2).unwrap();
+        writer.append(&other);
+        assert_eq!(writer.len(), 30);
+        assert_eq!(
+            writer.into_bytes(),
+            vec![0b10111100, 0b00111100, 0xBC, 0xDC]
+        );
+    }
+
+    #[cfg(feature = "std")]
+    proptest! {
+        #[test]
+        fn prop_written_bits_read_back(
+            fields in prop::collection::vec((0usize..=128, any::<u128>(), any::<bool>()), 0..32)
+        ) {
+            let mut writer = BitWriter::new();
+            for &(width, value, pad) in &fields {
+                writer.write_uint(value, width).unwrap();
+                if pad {
+                    writer.pad_to_byte();
+                }
+            }
+            let bytes = writer.into_bytes();
+            let mut bits = Bits::new(&bytes);
+            let mut position = 0;
+            for &(width, value, pad) in &fields {
+                let value = match width {
+                    0 => 0,
+                    width => value & (u128::MAX >> (128 - width)),
+                };
+                let expected = &value.to_be_bytes()[16 - byte_count(width)..];
+                prop_assert_eq!(bits.peek_uint::<u128>(width).unwrap(), value);
+                prop_assert_eq!(bits.take(width).unwrap(), expected);
+                position += width;
+                if pad && position % 8 != 0 {
+                    bits.take(8 - position % 8).unwrap();
+                    position += 8 - position % 8;
+                }
+            }
+            // The final byte is padded with zeros.
+            if position % 8 != 0 {
+                prop_assert_eq!(bits.take(8 - position % 8).unwrap(), vec![0]);
+            }
+            prop_assert!(bits.is_empty());
+        }
+    }
+
+    // Operations applied to both `Bits` and the reference model.
+    #[cfg(feature = "std")]
+    #[derive(Clone, Debug)]
+    enum Op {
+        Take(usize),
+        Peek(usize),
+        TakeUint(usize),
+        Skip(usize),
+        Split(usize),
+    }
+
+    #[cfg(feature = "std")]
+    fn op_strategy() -> impl Strategy<Value = Op> {
+        prop_oneof![
+            (0usize..80).prop_map(Op::Take),
+            (0usize..80).prop_map(Op::Peek),
+            (0usize..=128).prop_map(Op::TakeUint),
+            (0usize..40).prop_map(Op::Skip),
+            (0usize..80).prop_map(Op::Split),
+        ]
+    }
+
+    // Right-aligned big-endian bytes holding the given bits, as `take` returns them.
+    #[cfg(feature = "std")]
+    fn model_bytes(bits: &[bool]) -> Vec<u8> {
+        let mut bytes = vec![0u8; byte_count(bits.len())];
+        for (index, &bit) in bits.iter().rev().enumerate() {
+            let len = bytes.len();
+            bytes[len - 1 - index / 8] |= (bit as u8) << (index % 8);
+        }
+        bytes
+    }
+
+    #[cfg(feature = "std")]
+    proptest! {
+        #[test]
+        fn prop_matches_bool_model(
+            bytes in prop::collection::vec(any::<u8>(), 0..24),
+            ops in prop::collection::vec(op_strategy(), 0..24),
+        ) {
+            let model: Vec<bool> = bytes
+                .iter()
+                .flat_map(|&byte| (0..8).rev().map(move |bit| (byte >> bit) & 1 == 1))
+                .collect();
+            let mut bits = Bits::new(&bytes);
+            let mut cursor = 0;
+            let mut end = model.len();
+            for op in ops {
+                let remaining = end - cursor;
+                prop_assert_eq!(bits.len(), remaining);
+                prop_assert_eq!(bits.is_empty(), remaining == 0);
+                let requested = match op {
+                    Op::Take(n) | Op::Peek(n) | Op::TakeUint(n) | Op::Skip(n) | Op::Split(n) => n,
+                };
+                if requested > remaining {
+                    let error = BitsError::InsufficientBits {
+                        requested,
+                        available: remaining,
+                    };
+                    let actual = match op {
+                        Op::Take(n) => bits.take(n).map(|_| ()),
+                        Op::Peek(n) => bits.peek(n).map(|_| ()),
+                        Op::TakeUint(n) => bits.take_u128(n).map(|_| ()),
+                        Op::Skip(n) => bits.skip(n),
+                        Op::Split(n) => bits.split(n).map(|_| ()),
+                    };
+                    prop_assert_eq!(actual, Err(error));
+                    continue;
+                }
+                let expected = &model[cursor..cursor + requested];
+                match op {
+                    Op::Take(n) => prop_assert_eq!(bits.take(n)?, model_bytes(expected)),
+                    Op::Peek(n) => prop_assert_eq!(bits.peek(n)?, model_bytes(expected)),
+                    Op::TakeUint(n) => {
+                        let value = expected.iter().fold(0u128, |acc, &bit| acc << 1 | bit as u128);
+                        prop_assert_eq!(bits.take_u128(n)?, value);
+                    }
+                    Op::Skip(n) => bits.skip(n)?,
+                    // Continue with the sub-reader, whose end is the end of the split.
+                    Op::Split(n) => {
+                        bits = bits.split(n)?;
+                        end = cursor + n;
+                        prop_assert_eq!(bits.position(), 0);
+                    }
+                }
+                if !matches!(op, Op::Peek(_) | Op::Split(_)) {
+                    cursor += requested;
+                }
+            }
+        }
+    }
 }
@@ -108,7 +108,7 @@ impl std::str::FromStr for Grid {
             .lines()
             .flat_map(|line| {
                 let chars = line.trim().chars();
-                if width == None {
+                if width.is_none() {
                     width = Some(chars.clone().count() as u32);
                 }
                 chars.map(|ch| match ch {
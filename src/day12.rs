@@ -1,86 +1,530 @@
-use std::collections::HashMap;
-
-type CaveMap<'map> = HashMap<&'map str, Vec<&'map str>>;
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+use std::rc::Rc;
+use thiserror::Error;
 
 const INPUT: &str = include_str!("./assets/day12.txt");
 
 pub fn solve() -> String {
-    let map = build_map(INPUT);
+    let map = build_map(INPUT).expect("Puzzle input is well-formed.");
     format!("{}, {}", part1(&map), part2(&map))
 }
 
-fn build_map(input: &str) -> CaveMap {
-    let mut map = HashMap::new();
-    for line in input.lines().map(|l| l.trim()) {
-        let (a, b) = line.split_once('-').unwrap();
-        let a_leaves = map.entry(a).or_insert_with(Vec::new);
-        a_leaves.push(b);
-        let b_leaves = map.entry(b).or_insert_with(Vec::new);
-        b_leaves.push(a);
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct CaveId(usize);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Size {
+    Big,
+    Small,
+}
+
+// The cave system, with each cave name interned to a `CaveId` indexing its size and the
+// caves adjacent to it. Adjacent caves are sorted by name.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CaveMap<'map> {
+    names: Vec<&'map str>,
+    ids: HashMap<&'map str, CaveId>,
+    sizes: Vec<Size>,
+    adjacent: Vec<Vec<CaveId>>,
+}
+
+#[derive(Clone, Debug, Error, PartialEq)]
+pub enum MapError {
+    #[error("Line {line} is not of the form a-b, joining two different caves.")]
+    Malformed { line: usize },
+    #[error("The passage {0}-{1} appears more than once.")]
+    DuplicateEdge(String, String),
+    #[error("There is no cave named {0}.")]
+    UnknownCave(String),
+    #[error("Paths can loop around {} without limit.", .0.join("-"))]
+    InfinitePaths(Vec<String>),
+    #[error("There is no passage from {0} to {1}.")]
+    NoPassage(String, String),
+}
+
+// Parses the passages between caves, checking only that each line joins two caves and that no
+// passage is repeated. Which caves must exist and whether the paths are finite depend on the
+// policy, so those are checked by `CaveMap::validate`.
+pub fn build_map(input: &str) -> Result<CaveMap<'_>, MapError> {
+    let mut map = CaveMap {
+        names: Vec::new(),
+        ids: HashMap::new(),
+        sizes: Vec::new(),
+        adjacent: Vec::new(),
+    };
+    let mut edges = HashSet::new();
+    for (index, line) in input.lines().map(|l| l.trim()).enumerate() {
+        let malformed = MapError::Malformed { line: index + 1 };
+        let (a, b) = line.split_once('-').ok_or(malformed.clone())?;
+        if a.is_empty() || b.is_empty() || b.contains('-') || a == b {
+            return Err(malformed);
+        }
+        let (a, b) = (map.intern(a), map.intern(b));
+        if !edges.insert((a.min(b), a.max(b))) {
+            return Err(MapError::DuplicateEdge(
+                map.name(a).into(),
+                map.name(b).into(),
+            ));
+        }
+        map.adjacent[a.0].push(b);
+        map.adjacent[b.0].push(a);
     }
-    map
+    let names = map.names.clone();
+    for leaves in &mut map.adjacent {
+        leaves.sort_unstable_by_key(|leaf| names[leaf.0]);
+    }
+    Ok(map)
 }
 
-fn part1(map: &CaveMap) -> usize {
-    let mut partial_paths = vec![vec!["start"]];
-    let mut complete_paths = Vec::new();
-    while let Some(path) = partial_paths.pop() {
-        // Find all nodes adjacent to the last node in the path
-        let last_node = path.last().unwrap();
-        let adjoining = map.get(last_node).unwrap();
-        for next_node in adjoining {
-            // If the node is "end" it marks the end of a path and we push it to complete
-            if *next_node == "end" {
-                let mut complete = path.clone();
-                complete.push(next_node);
-                complete_paths.push(complete);
+impl<'map> CaveMap<'map> {
+    pub fn id(&self, name: &str) -> Option<CaveId> {
+        self.ids.get(name).copied()
+    }
+
+    pub fn name(&self, id: CaveId) -> &'map str {
+        self.names[id.0]
+    }
+
+    pub fn size(&self, id: CaveId) -> Size {
+        self.sizes[id.0]
+    }
+
+    pub fn adjacent(&self, id: CaveId) -> &[CaveId] {
+        &self.adjacent[id.0]
+    }
+
+    // Number of caves.
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    // Checks that the caves named by the policy exist, and that no two caves the policy lets a
+    // path enter without limit are adjacent, even where no path could reach them.
+    pub fn validate(&self, policy: &VisitPolicy) -> Result<(), MapError> {
+        let rules = policy.resolve(self)?;
+        let unlimited = |id: CaveId| rules.limits[id.0].is_none();
+        for a in (0..self.len()).map(CaveId).filter(|&a| unlimited(a)) {
+            if let Some(&b) = self.adjacent(a).iter().find(|&&b| unlimited(b)) {
+                let (a, b) = (self.name(a), self.name(b));
+                return Err(MapError::InfinitePaths(vec![a.into(), b.into(), a.into()]));
             }
-            // If the node is uppercase, we can visit it under any circumstance
-            // or, if it's not already in the list, we can visit it
-            else if &next_node.to_ascii_uppercase() == next_node || !path.contains(next_node) {
-                let mut partial = path.clone();
-                partial.push(next_node);
-                partial_paths.push(partial);
+        }
+        Ok(())
+    }
+
+    // Describes the map in the Graphviz DOT language, drawing big caves as boxes and small caves
+    // as ellipses. The caves and passages of `path`, if given, are drawn in red.
+    pub fn to_dot(&self, path: Option<&[&str]>) -> Result<String, MapError> {
+        let mut highlighted = HashSet::new();
+        let mut route = Vec::new();
+        for &name in path.unwrap_or_default() {
+            let id = self.id(name).ok_or(MapError::UnknownCave(name.into()))?;
+            if let Some(&last) = route.last() {
+                if !self.adjacent(last).contains(&id) {
+                    return Err(MapError::NoPassage(self.name(last).into(), name.into()));
+                }
+                highlighted.insert((id.min(last), id.max(last)));
             }
+            route.push(id);
         }
+
+        let mut dot = String::from("graph caves {\n");
+        for index in 0..self.len() {
+            let id = CaveId(index);
+            let shape = match self.size(id) {
+                Size::Big => "box",
+                Size::Small => "ellipse",
+            };
+            let name = quote(self.name(id));
+            if route.contains(&id) {
+                writeln!(dot, "    {name} [shape={shape}, {HIGHLIGHT}];").unwrap();
+            } else {
+                writeln!(dot, "    {name} [shape={shape}];").unwrap();
+            }
+        }
+        for index in 0..self.len() {
+            let id = CaveId(index);
+            for &leaf in self.adjacent(id).iter().filter(|&&leaf| id < leaf) {
+                let (a, b) = (quote(self.name(id)), quote(self.name(leaf)));
+                if highlighted.contains(&(id, leaf)) {
+                    writeln!(dot, "    {a} -- {b} [{HIGHLIGHT}];").unwrap();
+                } else {
+                    writeln!(dot, "    {a} -- {b};").unwrap();
+                }
+            }
+        }
+        dot.push_str("}\n");
+        Ok(dot)
     }
-    complete_paths.len()
+
+    fn intern(&mut self, name: &'map str) -> CaveId {
+        if let Some(id) = self.id(name) {
+            return id;
+        }
+        let id = CaveId(self.names.len());
+        let size = if name.bytes().any(|b| b.is_ascii_lowercase()) {
+            Size::Small
+        } else {
+            Size::Big
+        };
+        self.names.push(name);
+        self.ids.insert(name, id);
+        self.sizes.push(size);
+        self.adjacent.push(Vec::new());
+        id
+    }
+}
+
+// The DOT attributes for caves and passages on a highlighted path.
+const HIGHLIGHT: &str = "color=red, penwidth=2";
+
+// Quotes a cave name as a DOT identifier.
+fn quote(name: &str) -> String {
+    format!("\"{}\"", name.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn part1(map: &CaveMap) -> usize {
+    count_paths(map, &VisitPolicy::default()).expect("Puzzle input is well-formed.")
 }
 
 fn part2(map: &CaveMap) -> usize {
-    let mut partial_paths = vec![(vec!["start"], false)];
-    let mut complete_paths = Vec::new();
-    while let Some((path, small_visited)) = partial_paths.pop() {
-        // Find all nodes adjacent to the last node in the path
-        let last_node = path.last().unwrap();
-        let adjoining = map.get(last_node).unwrap();
-        for next_node in adjoining {
-            // If the node is "end" it marks the end of a path and we push it to complete
-            if *next_node == "end" {
-                let mut complete = path.clone();
-                complete.push(next_node);
-                complete_paths.push(complete);
+    count_paths(map, &VisitPolicy::with_revisits(1)).expect("Puzzle input is well-formed.")
+}
+
+// The rules for which caves a path may visit. Each cave may be entered up to its limit, and
+// beyond that by spending one of a budget of revisits shared by the whole path. Paths never
+// return to the start, and finish as soon as they reach the end.
+#[derive(Clone, Debug, PartialEq)]
+pub struct VisitPolicy<'p> {
+    pub start: &'p str,
+    pub end: &'p str,
+    pub revisits: usize,
+    // Overrides the default limit, which is one for small caves and unlimited for big caves.
+    pub limits: HashMap<&'p str, usize>,
+    pub forbidden: HashSet<&'p str>,
+}
+
+impl Default for VisitPolicy<'_> {
+    fn default() -> Self {
+        VisitPolicy {
+            start: "start",
+            end: "end",
+            revisits: 0,
+            limits: HashMap::new(),
+            forbidden: HashSet::new(),
+        }
+    }
+}
+
+impl<'p> VisitPolicy<'p> {
+    pub fn with_revisits(revisits: usize) -> VisitPolicy<'p> {
+        VisitPolicy {
+            revisits,
+            ..Default::default()
+        }
+    }
+
+    // Looks up the caves named by the policy, failing if any are not in the map.
+    fn resolve(&self, map: &CaveMap) -> Result<Rules, MapError> {
+        let id = |name: &str| map.id(name).ok_or(MapError::UnknownCave(name.into()));
+        let (start, end) = (id(self.start)?, id(self.end)?);
+        let mut limits: Vec<_> = (0..map.len())
+            .map(|index| match map.size(CaveId(index)) {
+                Size::Small => Some(1),
+                Size::Big => None,
+            })
+            .collect();
+        for (&name, &limit) in &self.limits {
+            limits[id(name)?.0] = Some(limit);
+        }
+        for &name in &self.forbidden {
+            limits[id(name)?.0] = Some(0);
+        }
+        let startable = limits[start.0] != Some(0);
+        let reachable = limits[end.0] != Some(0) && start != end;
+        limits[start.0] = Some(0);
+        Ok(Rules {
+            start: startable.then_some(start),
+            end: reachable.then_some(end),
+            limits,
+            revisits: self.revisits,
+        })
+    }
+}
+
+// A policy in terms of cave IDs. The start and end are `None` if no path can use them.
+struct Rules {
+    start: Option<CaveId>,
+    end: Option<CaveId>,
+    // The number of times a path may enter each cave without spending a revisit, or `None` if
+    // there is no limit. A revisit may only be spent on a cave with a limit of at least one.
+    limits: Vec<Option<usize>>,
+    revisits: usize,
+}
+
+// Counts the paths allowed by the policy without building them, failing if there are
+// infinitely many because caves without a limit are adjacent.
+pub fn count_paths(map: &CaveMap, policy: &VisitPolicy) -> Result<usize, MapError> {
+    PathCounter::new(map, policy.resolve(map)?).total()
+}
+
+// Memoises the number of ways to reach the end from a cave, given how many times each limited
+// cave has been visited and the revisits remaining.
+struct PathCounter<'a> {
+    map: &'a CaveMap<'a>,
+    rules: Rules,
+    // Where each cave that can be entered a limited number of times counts its visits in
+    // `visits`, which packs a cave with a limit of one into a single bit.
+    fields: Vec<Option<Field>>,
+    visits: Vec<u64>,
+    memo: HashMap<State, Memo>,
+    // The caves being counted from, in the order they were entered.
+    route: Vec<CaveId>,
+    // The cycles found back to a state being counted from, which mean there are infinitely many
+    // paths if that state has any way to reach the end.
+    loops: HashMap<State, Vec<CaveId>>,
+    infinite: Option<Vec<CaveId>>,
+}
+
+// The visits are shared between the keys of `memo` and `loops` rather than copied.
+type State = (CaveId, Rc<[u64]>, usize);
+
+#[derive(Clone, Copy)]
+enum Memo {
+    // Still being counted from, by the cave at this index of the route.
+    Counting(usize),
+    Counted(usize),
+}
+
+// A counter of `mask` bits, shifted by `shift` within a word of the visits.
+#[derive(Clone, Copy)]
+struct Field {
+    word: usize,
+    shift: u32,
+    mask: u64,
+}
+
+impl<'a> PathCounter<'a> {
+    fn new(map: &'a CaveMap<'a>, rules: Rules) -> PathCounter<'a> {
+        // Only caves that can be entered a limited number of times need counting, in just
+        // enough bits to reach their limit. A field never straddles two words.
+        let mut fields = vec![None; map.len()];
+        let mut bits = 0;
+        for (field, &limit) in fields.iter_mut().zip(&rules.limits) {
+            let Some(limit @ 1..) = limit else {
+                continue;
+            };
+            let width = u64::BITS - (limit as u64).leading_zeros();
+            if bits % 64 + width > 64 {
+                bits = bits.next_multiple_of(64);
+            }
+            *field = Some(Field {
+                word: (bits / 64) as usize,
+                shift: bits % 64,
+                mask: u64::MAX >> (64 - width),
+            });
+            bits += width;
+        }
+        PathCounter {
+            map,
+            rules,
+            fields,
+            visits: vec![0; bits.div_ceil(64) as usize],
+            memo: HashMap::new(),
+            route: Vec::new(),
+            loops: HashMap::new(),
+            infinite: None,
+        }
+    }
+
+    // Counts the paths from the start, which also counts from every state a path can reach.
+    fn total(&mut self) -> Result<usize, MapError> {
+        let Some(start) = self.rules.start else {
+            return Ok(0);
+        };
+        let count = self.count(start, self.rules.revisits);
+        match &self.infinite {
+            Some(cycle) => Err(MapError::InfinitePaths(
+                cycle.iter().map(|&id| self.map.name(id).into()).collect(),
+            )),
+            None => Ok(count),
+        }
+    }
+
+    // Whether a path may enter the cave, and if so whether doing so spends a revisit.
+    fn entry(&self, cave: CaveId, revisits: usize) -> Option<bool> {
+        let Some(limit) = self.rules.limits[cave.0] else {
+            return Some(false);
+        };
+        let visits = self.fields[cave.0].map_or(0, |field| {
+            ((self.visits[field.word] >> field.shift) & field.mask) as usize
+        });
+        if visits < limit {
+            Some(false)
+        } else if limit > 0 && revisits > 0 {
+            Some(true)
+        } else {
+            None
+        }
+    }
+
+    // Entering a cave by spending a revisit leaves its count at the limit, since the revisits
+    // remaining are what decide whether it can be entered again.
+    fn enter(&mut self, cave: CaveId, revisit: bool) {
+        if let (Some(field), false) = (self.fields[cave.0], revisit) {
+            self.visits[field.word] += 1 << field.shift;
+        }
+    }
+
+    fn leave(&mut self, cave: CaveId, revisit: bool) {
+        if let (Some(field), false) = (self.fields[cave.0], revisit) {
+            self.visits[field.word] -= 1 << field.shift;
+        }
+    }
+
+    fn count(&mut self, cave: CaveId, revisits: usize) -> usize {
+        let key = (cave, Rc::from(&self.visits[..]), revisits);
+        let memo = self.memo.get(&key).copied();
+        if let Some(Memo::Counted(count)) = memo {
+            return count;
+        }
+        if self.infinite.is_some() {
+            return 0;
+        }
+        // A path can only return to a state by going round caves without a limit. Until it is
+        // known whether the state has any way to the end, the loop counts as having none.
+        if let Some(Memo::Counting(index)) = memo {
+            let mut cycle = self.route[index..].to_vec();
+            cycle.push(cave);
+            self.loops.entry(key).or_insert(cycle);
+            return 0;
+        }
+        self.memo
+            .insert(key.clone(), Memo::Counting(self.route.len()));
+        self.route.push(cave);
+        let map = self.map;
+        let mut total = 0;
+        for &next in map.adjacent(cave) {
+            if Some(next) == self.rules.end {
+                total += 1;
+                continue;
             }
-            // Drop the path trying to return to start, as that isn't allowed
-            else if *next_node == "start" {
+            let Some(revisit) = self.entry(next, revisits) else {
                 continue;
+            };
+            self.enter(next, revisit);
+            total += self.count(next, revisits - revisit as usize);
+            self.leave(next, revisit);
+        }
+        self.route.pop();
+        if let Some(cycle) = self.loops.remove(&key) {
+            if total > 0 && self.infinite.is_none() {
+                self.infinite = Some(cycle);
             }
-            // If the node is uppercase, we can visit it under any circumstance
-            // if it's a small cave, we can only visit it once
-            else if &next_node.to_ascii_uppercase() == next_node || !path.contains(next_node) {
-                let mut partial = path.clone();
-                partial.push(next_node);
-                partial_paths.push((partial, small_visited));
+        }
+        self.memo.insert(key, Memo::Counted(total));
+        total
+    }
+}
+
+// Lazily yields every path allowed by the policy, as the sequence of caves visited. Paths come
+// in lexicographic order of their cave names. Fails if there are infinitely many paths, which
+// is found by counting them first. The counts then steer the walk away from caves that lead
+// nowhere, including loops through caves without a limit.
+pub fn paths<'a, 'map>(
+    map: &'a CaveMap<'map>,
+    policy: &VisitPolicy,
+) -> Result<Paths<'a, 'map>, MapError> {
+    let mut counter = PathCounter::new(map, policy.resolve(map)?);
+    counter.total()?;
+    let mut paths = Paths {
+        map,
+        path: Vec::new(),
+        steps: Vec::new(),
+        revisits: counter.rules.revisits,
+        counter,
+    };
+    // The counter counts from the start without having entered it.
+    if let Some(start) = paths.counter.rules.start {
+        paths.path.push(start);
+        paths.steps.push(Step {
+            next: 0,
+            revisit: false,
+        });
+    }
+    Ok(paths)
+}
+
+// A depth-first walk of the cave system, holding only the path currently being explored.
+pub struct Paths<'a, 'map> {
+    map: &'a CaveMap<'map>,
+    counter: PathCounter<'a>,
+    path: Vec<CaveId>,
+    steps: Vec<Step>,
+    revisits: usize,
+}
+
+// The progress made from a cave in the path.
+struct Step {
+    // The index of the next leaf to try.
+    next: usize,
+    // Whether entering the cave spent a revisit.
+    revisit: bool,
+}
+
+impl Paths<'_, '_> {
+    fn push(&mut self, cave: CaveId, revisit: bool) {
+        self.counter.enter(cave, revisit);
+        if revisit {
+            self.revisits -= 1;
+        }
+        self.path.push(cave);
+        self.steps.push(Step { next: 0, revisit });
+    }
+
+    fn pop(&mut self) {
+        let cave = self.path.pop().unwrap();
+        let step = self.steps.pop().unwrap();
+        if step.revisit {
+            self.revisits += 1;
+        }
+        // The start was never entered.
+        if !self.path.is_empty() {
+            self.counter.leave(cave, step.revisit);
+        }
+    }
+}
+
+impl<'map> Iterator for Paths<'_, 'map> {
+    type Item = Vec<&'map str>;
+
+    fn next(&mut self) -> Option<Vec<&'map str>> {
+        while let Some(&cave) = self.path.last() {
+            let step = self.steps.last_mut().unwrap();
+            let Some(&leaf) = self.map.adjacent(cave).get(step.next) else {
+                self.pop();
+                continue;
+            };
+            step.next += 1;
+            if Some(leaf) == self.counter.rules.end {
+                let mut complete: Vec<_> = self.path.iter().map(|&id| self.map.name(id)).collect();
+                complete.push(self.map.name(leaf));
+                return Some(complete);
             }
-            // We can visit one small cave twice
-            else if !small_visited && path.contains(next_node) {
-                let mut partial = path.clone();
-                partial.push(next_node);
-                partial_paths.push((partial, true));
+            if let Some(revisit) = self.counter.entry(leaf, self.revisits) {
+                self.push(leaf, revisit);
+                if self.counter.count(leaf, self.revisits) == 0 {
+                    self.pop();
+                }
             }
         }
+        None
     }
-    complete_paths.len()
 }
 
 #[cfg(test)]
@@ -128,9 +572,9 @@ mod tests {
     #[test]
     fn test1() {
         let tests = [
-            (build_map(EX1), 10),
-            (build_map(EX2), 19),
-            (build_map(EX3), 226),
+            (build_map(EX1).unwrap(), 10),
+            (build_map(EX2).unwrap(), 19),
+            (build_map(EX3).unwrap(), 226),
         ];
 
         for (map, expected) in tests {
@@ -141,13 +585,302 @@ mod tests {
     #[test]
     fn test2() {
         let tests = [
-            (build_map(EX1), 36),
-            (build_map(EX2), 103),
-            (build_map(EX3), 3509),
+            (build_map(EX1).unwrap(), 36),
+            (build_map(EX2).unwrap(), 103),
+            (build_map(EX3).unwrap(), 3509),
         ];
 
         for (map, expected) in tests {
             assert_eq!(part2(&map), expected);
         }
     }
+
+    #[test]
+    fn test_build_map() {
+        let map = build_map(EX1).unwrap();
+        assert_eq!(map.len(), 6);
+        let a = map.id("A").unwrap();
+        assert_eq!(map.name(a), "A");
+        assert_eq!(map.size(a), Size::Big);
+        assert_eq!(map.size(map.id("start").unwrap()), Size::Small);
+        let leaves: Vec<_> = map.adjacent(a).iter().map(|&id| map.name(id)).collect();
+        assert_eq!(leaves, ["b", "c", "end", "start"]);
+        assert_eq!(map.id("B"), None);
+
+        let errors = [
+            ("start-A\nA", MapError::Malformed { line: 2 }),
+            ("start-A\nA-b-c", MapError::Malformed { line: 2 }),
+            ("start-", MapError::Malformed { line: 1 }),
+            ("b-b", MapError::Malformed { line: 1 }),
+            (
+                "start-A\nA-end\nend-A",
+                MapError::DuplicateEdge("end".into(), "A".into()),
+            ),
+        ];
+        for (input, error) in errors {
+            assert_eq!(build_map(input), Err(error));
+        }
+
+        // Caves the policy names are only looked up once it is known.
+        let map = build_map("a-b").unwrap();
+        let policy = VisitPolicy::default();
+        assert_eq!(
+            map.validate(&policy),
+            Err(MapError::UnknownCave("start".into()))
+        );
+        let policy = VisitPolicy {
+            start: "a",
+            end: "b",
+            limits: HashMap::from([("c", 2)]),
+            ..Default::default()
+        };
+        assert_eq!(
+            map.validate(&policy),
+            Err(MapError::UnknownCave("c".into()))
+        );
+        let policy = VisitPolicy {
+            start: "a",
+            end: "b",
+            ..Default::default()
+        };
+        assert_eq!(map.validate(&policy), Ok(()));
+        assert_eq!(
+            build_map(EX1).unwrap().validate(&VisitPolicy::default()),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_to_dot() {
+        let map = build_map("start-A\nA-b\nb-end\nA-end").unwrap();
+        let expected = r#"graph caves {
+    "start" [shape=ellipse];
+    "A" [shape=box];
+    "b" [shape=ellipse];
+    "end" [shape=ellipse];
+    "start" -- "A";
+    "A" -- "b";
+    "A" -- "end";
+    "b" -- "end";
+}
+"#;
+        assert_eq!(map.to_dot(None).unwrap(), expected);
+
+        let path = ["start", "A", "b", "end"];
+        let expected = r#"graph caves {
+    "start" [shape=ellipse, color=red, penwidth=2];
+    "A" [shape=box, color=red, penwidth=2];
+    "b" [shape=ellipse, color=red, penwidth=2];
+    "end" [shape=ellipse, color=red, penwidth=2];
+    "start" -- "A" [color=red, penwidth=2];
+    "A" -- "b" [color=red, penwidth=2];
+    "A" -- "end";
+    "b" -- "end" [color=red, penwidth=2];
+}
+"#;
+        assert_eq!(map.to_dot(Some(&path)).unwrap(), expected);
+        assert_eq!(
+            map.to_dot(Some(&["start", "b"])),
+            Err(MapError::NoPassage("start".into(), "b".into()))
+        );
+        assert_eq!(
+            map.to_dot(Some(&["start", "c"])),
+            Err(MapError::UnknownCave("c".into()))
+        );
+        assert_eq!(quote(r#"a"b\c"#), r#""a\"b\\c""#);
+    }
+
+    #[test]
+    fn test_paths() {
+        let map = build_map(EX1).unwrap();
+        let expected = [
+            "start,A,b,A,c,A,end",
+            "start,A,b,A,end",
+            "start,A,b,end",
+            "start,A,c,A,b,A,end",
+            "start,A,c,A,b,end",
+            "start,A,c,A,end",
+            "start,A,end",
+            "start,b,A,c,A,end",
+            "start,b,A,end",
+            "start,b,end",
+        ];
+        let actual: Vec<_> = paths(&map, &VisitPolicy::default())
+            .unwrap()
+            .map(|path| path.join(","))
+            .collect();
+        assert_eq!(actual, expected);
+
+        for (input, expected) in [(EX1, 36), (EX2, 103), (EX3, 3509)] {
+            let map = build_map(input).unwrap();
+            let all: Vec<_> = paths(&map, &VisitPolicy::with_revisits(1))
+                .unwrap()
+                .collect();
+            assert_eq!(all.len(), expected);
+            assert!(all.windows(2).all(|pair| pair[0] < pair[1]));
+        }
+        let map = build_map(EX3).unwrap();
+        let first = paths(&map, &VisitPolicy::with_revisits(1))
+            .unwrap()
+            .next()
+            .unwrap();
+        assert_eq!(
+            first,
+            ["start", "DX", "fs", "DX", "fs", "DX", "he", "DX", "pj", "RW", "zg", "end"]
+        );
+    }
+
+    #[test]
+    fn test_visit_policies() {
+        let map = build_map(EX1).unwrap();
+        let check = |policy: VisitPolicy, expected| {
+            assert_eq!(count_paths(&map, &policy), Ok(expected));
+            assert_eq!(paths(&map, &policy).unwrap().count(), expected);
+        };
+        check(VisitPolicy::with_revisits(0), 10);
+        check(VisitPolicy::with_revisits(1), 36);
+        check(VisitPolicy::with_revisits(2), 101);
+
+        // Giving each small cave a limit of two is more generous than a single revisit.
+        let limits = HashMap::from([("b", 2), ("c", 2), ("d", 2)]);
+        let policy = VisitPolicy {
+            limits,
+            ..Default::default()
+        };
+        check(policy, 54);
+
+        // Unreachable caves, interned first, push the visit counts of b, c and d to the end of
+        // the first word: one bit for p0, and two for each other cave with a limit of three.
+        let mut input = String::new();
+        for index in 0..16 {
+            writeln!(input, "p{index}-q{index}").unwrap();
+        }
+        input.push_str(EX1);
+        let padded = build_map(&input).unwrap();
+        let mut limits = HashMap::from([("b", 2), ("c", 2), ("d", 2)]);
+        let names: Vec<_> = (0..16)
+            .flat_map(|index| [format!("p{index}"), format!("q{index}")])
+            .skip(1)
+            .collect();
+        limits.extend(names.iter().map(|name| (name.as_str(), 3)));
+        let policy = VisitPolicy {
+            limits,
+            ..Default::default()
+        };
+        assert_eq!(count_paths(&padded, &policy), Ok(54));
+        assert_eq!(paths(&padded, &policy).unwrap().count(), 54);
+
+        // Visiting the big cave once leaves no way back from c or d.
+        let policy = VisitPolicy {
+            limits: HashMap::from([("A", 1)]),
+            ..Default::default()
+        };
+        let routes: Vec<_> = paths(&map, &policy)
+            .unwrap()
+            .map(|path| path.join(","))
+            .collect();
+        assert_eq!(
+            routes,
+            [
+                "start,A,b,end",
+                "start,A,end",
+                "start,b,A,end",
+                "start,b,end"
+            ]
+        );
+
+        let policy = VisitPolicy {
+            forbidden: HashSet::from(["A"]),
+            ..Default::default()
+        };
+        check(policy, 1);
+        let policy = VisitPolicy {
+            forbidden: HashSet::from(["start"]),
+            ..Default::default()
+        };
+        check(policy, 0);
+
+        // Swapping the ends gives each path in reverse.
+        let policy = VisitPolicy {
+            start: "end",
+            end: "start",
+            ..Default::default()
+        };
+        check(policy, 10);
+        let policy = VisitPolicy {
+            start: "b",
+            end: "d",
+            ..Default::default()
+        };
+        check(policy, 1);
+        let policy = VisitPolicy {
+            forbidden: HashSet::from(["missing"]),
+            ..Default::default()
+        };
+        let error = MapError::UnknownCave("missing".into());
+        assert_eq!(count_paths(&map, &policy), Err(error.clone()));
+        assert_eq!(paths(&map, &policy).err(), Some(error));
+    }
+
+    #[test]
+    fn test_infinite_paths() {
+        let map = build_map("start-A\nA-BC\nBC-end").unwrap();
+        let error = MapError::InfinitePaths(vec!["A".into(), "BC".into(), "A".into()]);
+        assert_eq!(
+            count_paths(&map, &VisitPolicy::default()),
+            Err(error.clone())
+        );
+        assert_eq!(
+            error.to_string(),
+            "Paths can loop around A-BC-A without limit."
+        );
+        assert_eq!(
+            paths(&map, &VisitPolicy::default()).err(),
+            Some(error.clone())
+        );
+        assert_eq!(map.validate(&VisitPolicy::default()), Err(error));
+
+        // Limiting either big cave bounds the paths, here to those entering A once or twice.
+        let policy = VisitPolicy {
+            limits: HashMap::from([("A", 2)]),
+            ..Default::default()
+        };
+        assert_eq!(count_paths(&map, &policy), Ok(2));
+        assert_eq!(paths(&map, &policy).unwrap().count(), 2);
+        assert_eq!(map.validate(&policy), Ok(()));
+
+        // The loop can only be reached by a path that must then go back through b.
+        let map = build_map("start-b\nb-end\nb-A\nA-BC").unwrap();
+        assert_eq!(count_paths(&map, &VisitPolicy::default()), Ok(1));
+        let error = MapError::InfinitePaths(vec!["A".into(), "BC".into(), "A".into()]);
+        assert_eq!(
+            count_paths(&map, &VisitPolicy::with_revisits(1)),
+            Err(error)
+        );
+        assert_eq!(paths(&map, &VisitPolicy::default()).unwrap().count(), 1);
+
+        // The loop is a dead end, which the walk must not enter, though validation still
+        // rejects it.
+        let map = build_map("start-A\nA-BC\nstart-end").unwrap();
+        assert_eq!(count_paths(&map, &VisitPolicy::default()), Ok(1));
+        assert!(map.validate(&VisitPolicy::default()).is_err());
+        let all: Vec<_> = paths(&map, &VisitPolicy::default()).unwrap().collect();
+        assert_eq!(all, [["start", "end"]]);
+    }
+
+    #[test]
+    fn test_many_small_caves() {
+        // A chain of 100 small caves joined by big caves.
+        let mut input = String::from("start-c0\nc99-end");
+        for index in 0..99 {
+            input += &format!("\nc{index}-B{index}\nB{index}-c{}", index + 1);
+        }
+        let map = build_map(&input).unwrap();
+        assert_eq!(count_paths(&map, &VisitPolicy::default()), Ok(1));
+        // Each cave but the first and last can be revisited from the big cave on either side.
+        assert_eq!(
+            count_paths(&map, &VisitPolicy::with_revisits(1)),
+            Ok(1 + 2 * 100 - 2)
+        );
+    }
 }
@@ -1,11 +1,16 @@
-use std::{collections::HashSet, fmt::Display};
+use crate::bits::BitWriter;
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::{Display, Write},
+};
+use thiserror::Error;
 
 const INPUT: &str = include_str!("./assets/day13.txt");
 
 pub fn solve() -> String {
     let (paper, instructions) = parse(INPUT);
     format!(
-        "{},\n{}",
+        "{}, {}",
         part1(paper.clone(), &instructions),
         part2(paper, &instructions)
     )
@@ -20,64 +25,258 @@ fn part2(mut paper: Paper, instructions: &[Fold]) -> String {
     for instruction in instructions {
         paper.fold(*instruction);
     }
-    //dbg!(paper.coords);
-    format!("{}", paper)
-    //"TODO!".to_string()
+    paper
+        .read(&GlyphTable::default())
+        .expect("Puzzle input is well-formed.")
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
-enum Fold {
+pub enum Fold {
     X(u64),
     Y(u64),
 }
 
 #[derive(Clone, Debug, Default, PartialEq)]
-struct Paper {
+pub struct Paper {
+    width: u64,
+    height: u64,
+    coords: HashSet<(u64, u64)>,
+    // The paper as it was before each fold, so that folds can be undone.
+    history: Vec<Unfolded>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Unfolded {
+    fold: Fold,
     width: u64,
     height: u64,
     coords: HashSet<(u64, u64)>,
 }
 
 impl Paper {
-    fn fold(&mut self, fold: Fold) {
-        match fold {
-            Fold::X(pos) => {
-                let left_width = pos;
-                let right_width = self.width - pos - 1;
-                self.width = std::cmp::max(left_width, right_width);
-                let left_offset = self.width - left_width;
-                self.coords = self
-                    .coords
-                    .iter()
-                    .filter_map(|(x, y)| {
-                        use std::cmp::Ordering::*;
-                        match x.cmp(&pos) {
-                            Less => Some((x + left_offset, *y)),
-                            Greater => Some((2 * pos - x, *y)),
-                            Equal => None,
-                        }
-                    })
-                    .collect()
-            }
-            Fold::Y(pos) => {
-                let top_height = pos;
-                let bottom_height = self.height - pos - 1;
-                self.height = std::cmp::max(top_height, bottom_height);
-                let top_offset = self.height - top_height;
-                self.coords = self
-                    .coords
-                    .iter()
-                    .filter_map(|(x, y)| {
-                        use std::cmp::Ordering::*;
-                        match y.cmp(&pos) {
-                            Less => Some((*x, y + top_offset)),
-                            Greater => Some((*x, 2 * pos - y)),
-                            Equal => None,
-                        }
-                    })
-                    .collect()
+    // Folds the right or bottom part over onto the left or top part. The result is as long as
+    // the longer of the two parts, so a fold need not be at the midpoint, and paper beyond the
+    // edge counts as blank. Dots on the fold line itself are lost in the crease.
+    pub fn fold(&mut self, fold: Fold) {
+        let (width, height) = match fold {
+            Fold::X(pos) => (folded_length(self.width, pos), self.height),
+            Fold::Y(pos) => (self.width, folded_length(self.height, pos)),
+        };
+        let coords = self
+            .coords
+            .iter()
+            .filter_map(|&(x, y)| match fold {
+                Fold::X(pos) => folded_coord(x, pos, width).map(|x| (x, y)),
+                Fold::Y(pos) => folded_coord(y, pos, height).map(|y| (x, y)),
+            })
+            .collect();
+        self.history.push(Unfolded {
+            fold,
+            width: self.width,
+            height: self.height,
+            coords: std::mem::replace(&mut self.coords, coords),
+        });
+        self.width = width;
+        self.height = height;
+    }
+
+    // Undoes the last fold, restoring any dots that it merged or lost, and returns it.
+    pub fn unfold(&mut self) -> Option<Fold> {
+        let unfolded = self.history.pop()?;
+        self.width = unfolded.width;
+        self.height = unfolded.height;
+        self.coords = unfolded.coords;
+        Some(unfolded.fold)
+    }
+
+    // The folds made so far, in order.
+    pub fn folds(&self) -> impl Iterator<Item = Fold> + '_ {
+        self.history.iter().map(|unfolded| unfolded.fold)
+    }
+
+    // Yields a copy of the paper after each fold in turn, leaving this paper unfolded. The
+    // copies have no history, so they cannot be unfolded.
+    pub fn steps<'f>(&self, folds: &'f [Fold]) -> impl Iterator<Item = Paper> + 'f {
+        let paper = Paper {
+            width: self.width,
+            height: self.height,
+            coords: self.coords.clone(),
+            history: Vec::new(),
+        };
+        folds.iter().scan(paper, |paper, &fold| {
+            paper.fold(fold);
+            paper.history.clear();
+            Some(paper.clone())
+        })
+    }
+
+    pub fn dot_count(&self) -> usize {
+        self.coords.len()
+    }
+}
+
+impl Paper {
+    // Reads the letters drawn by the dots, each one glyph wide with a blank column between
+    // them. The last letter may omit its blank column.
+    pub fn read(&self, table: &GlyphTable) -> Result<String, OcrError> {
+        if self.height != table.height {
+            return Err(OcrError::Height {
+                height: self.height,
+                expected: table.height,
+            });
+        }
+        let pitch = table.width + 1;
+        (0..self.width.div_ceil(pitch))
+            .map(|index| {
+                let left = index * pitch;
+                let glyph: Vec<_> = (0..table.height)
+                    .flat_map(|y| (left..left + table.width).map(move |x| (x, y)))
+                    .map(|coord| self.coords.contains(&coord))
+                    .collect();
+                table
+                    .glyphs
+                    .get(&glyph)
+                    .copied()
+                    .ok_or(OcrError::Unrecognised { index })
+            })
+            .collect()
+    }
+}
+
+// Exports to image formats, drawing each dot as a black square `scale` pixels wide.
+impl Paper {
+    // A binary PBM image, in which each row is packed into bytes MSB-first.
+    pub fn to_pbm(&self, scale: u64) -> Vec<u8> {
+        let mut image =
+            format!("P4\n{} {}\n", self.width * scale, self.height * scale).into_bytes();
+        let mut pixels = BitWriter::new();
+        for y in 0..self.height * scale {
+            for x in 0..self.width * scale {
+                pixels.write_bit(self.coords.contains(&(x / scale, y / scale)));
             }
+            pixels.pad_to_byte();
+        }
+        image.extend(pixels.into_bytes());
+        image
+    }
+
+    // An SVG image with a white background, and a black square for each dot.
+    pub fn to_svg(&self, scale: u64) -> String {
+        let (width, height) = (self.width * scale, self.height * scale);
+        let mut svg = format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{width}\" height=\"{height}\">\n"
+        );
+        writeln!(
+            svg,
+            "  <rect width=\"{width}\" height=\"{height}\" fill=\"white\"/>"
+        )
+        .unwrap();
+        let mut coords: Vec<_> = self.coords.iter().collect();
+        coords.sort_unstable_by_key(|&&(x, y)| (y, x));
+        for (x, y) in coords {
+            let (x, y) = (x * scale, y * scale);
+            writeln!(
+                svg,
+                "  <rect x=\"{x}\" y=\"{y}\" width=\"{scale}\" height=\"{scale}\" fill=\"black\"/>"
+            )
+            .unwrap();
+        }
+        svg.push_str("</svg>\n");
+        svg
+    }
+}
+
+// The letters that may be drawn on the paper, as patterns of dots.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GlyphTable {
+    width: u64,
+    height: u64,
+    glyphs: HashMap<Vec<bool>, char>,
+}
+
+#[derive(Clone, Debug, Error, PartialEq)]
+pub enum OcrError {
+    #[error("Paper is {height} dots tall, but the glyphs are {expected} dots tall.")]
+    Height { height: u64, expected: u64 },
+    #[error("The glyph for {letter:?} is not {width} by {height}.")]
+    GlyphSize {
+        letter: char,
+        width: u64,
+        height: u64,
+    },
+    #[error("Letter {index} is not in the glyph table.")]
+    Unrecognised { index: u64 },
+}
+
+// The 4 by 6 letters drawn by the puzzle inputs.
+const LETTERS: [(char, [&str; 6]); 17] = [
+    ('A', [".##.", "#..#", "#..#", "####", "#..#", "#..#"]),
+    ('B', ["###.", "#..#", "###.", "#..#", "#..#", "###."]),
+    ('C', [".##.", "#..#", "#...", "#...", "#..#", ".##."]),
+    ('E', ["####", "#...", "###.", "#...", "#...", "####"]),
+    ('F', ["####", "#...", "###.", "#...", "#...", "#..."]),
+    ('G', [".##.", "#..#", "#...", "#.##", "#..#", ".###"]),
+    ('H', ["#..#", "#..#", "####", "#..#", "#..#", "#..#"]),
+    ('I', [".###", "..#.", "..#.", "..#.", "..#.", ".###"]),
+    ('J', ["..##", "...#", "...#", "...#", "#..#", ".##."]),
+    ('K', ["#..#", "#.#.", "##..", "#.#.", "#.#.", "#..#"]),
+    ('L', ["#...", "#...", "#...", "#...", "#...", "####"]),
+    ('O', [".##.", "#..#", "#..#", "#..#", "#..#", ".##."]),
+    ('P', ["###.", "#..#", "#..#", "###.", "#...", "#..."]),
+    ('R', ["###.", "#..#", "#..#", "###.", "#.#.", "#..#"]),
+    ('S', [".###", "#...", "#...", ".##.", "...#", "###."]),
+    ('U', ["#..#", "#..#", "#..#", "#..#", "#..#", ".##."]),
+    ('Z', ["####", "...#", "..#.", ".#..", "#...", "####"]),
+];
+
+impl Default for GlyphTable {
+    fn default() -> Self {
+        let mut table = GlyphTable::new(4, 6);
+        for (letter, rows) in LETTERS {
+            table.insert(letter, &rows).unwrap();
+        }
+        table
+    }
+}
+
+impl GlyphTable {
+    pub fn new(width: u64, height: u64) -> GlyphTable {
+        GlyphTable {
+            width,
+            height,
+            glyphs: HashMap::new(),
+        }
+    }
+
+    // Adds a letter drawn as rows of '#' for dots and any other character for blanks.
+    pub fn insert(&mut self, letter: char, rows: &[&str]) -> Result<(), OcrError> {
+        let width = self.width as usize;
+        if rows.len() as u64 != self.height || rows.iter().any(|row| row.chars().count() != width) {
+            return Err(OcrError::GlyphSize {
+                letter,
+                width: self.width,
+                height: self.height,
+            });
         }
+        let glyph = rows.iter().flat_map(|row| row.chars().map(|c| c == '#'));
+        self.glyphs.insert(glyph.collect(), letter);
+        Ok(())
+    }
+}
+
+// The length of a side of the paper after folding it at `pos`.
+fn folded_length(length: u64, pos: u64) -> u64 {
+    std::cmp::max(pos, length.saturating_sub(pos + 1))
+}
+
+// Where a coordinate ends up after folding at `pos`, measured from the far edge of the
+// folded paper, which lies along the fold line.
+fn folded_coord(coord: u64, pos: u64, length: u64) -> Option<u64> {
+    use std::cmp::Ordering::*;
+    match coord.cmp(&pos) {
+        Less => Some(length - (pos - coord)),
+        Greater => Some(length - (coord - pos)),
+        Equal => None,
     }
 }
 
@@ -97,7 +296,7 @@ impl Display for Paper {
     }
 }
 
-fn parse(input: &str) -> (Paper, Vec<Fold>) {
+pub fn parse(input: &str) -> (Paper, Vec<Fold>) {
     let (paper, instructions) = match input.split_once("\r\n\r\n") {
         Some(data) => data,
         None => input.split_once("\n\n").unwrap(),
@@ -124,6 +323,7 @@ fn parse(input: &str) -> (Paper, Vec<Fold>) {
         width: max_x + 1,
         height: max_y + 1,
         coords,
+        ..Default::default()
     };
 
     let instructions: Vec<_> = instructions
@@ -192,6 +392,7 @@ mod tests {
                 (8, 10),
                 (9, 0),
             ]),
+            ..Default::default()
         };
         let exp_instructions = vec![Fold::Y(7), Fold::X(5)];
         let (act_paper, act_instructions) = parse(EX);
@@ -204,4 +405,193 @@ mod tests {
         let (paper, instructions) = parse(EX);
         assert_eq!(part1(paper, &instructions), 17);
     }
+
+    #[test]
+    fn test2() {
+        // "HI", with each dot mirrored into one of the four quarters that the folds bring
+        // together.
+        let input = "0,0
+                          15,0
+                          0,11
+                          15,11
+                          0,2
+                          17,2
+                          2,10
+                          15,10
+                          0,3
+                          15,3
+                          0,8
+                          15,8
+                          0,5
+                          15,5
+                          6,12
+                          11,12
+                          8,0
+                          11,1
+                          7,10
+                          11,9
+                          7,4
+                          12,5
+                          7,7
+                          10,7
+
+                          fold along y=6
+                          fold along x=9";
+        let (paper, instructions) = parse(input);
+        assert_eq!((paper.width, paper.height), (18, 13));
+        assert_eq!(part2(paper, &instructions), "HI");
+    }
+
+    #[test]
+    fn test_steps() {
+        let (paper, instructions) = parse(EX);
+        let steps: Vec<_> = paper.steps(&instructions).collect();
+        assert_eq!(paper.dot_count(), 18);
+        let counts: Vec<_> = steps.iter().map(Paper::dot_count).collect();
+        assert_eq!(counts, [17, 16]);
+        assert_eq!((steps[0].width, steps[0].height), (11, 7));
+        assert_eq!(steps[1].folds().count(), 0);
+
+        let rows = [
+            "▉▉▉▉▉",
+            "▉   ▉",
+            "▉   ▉",
+            "▉   ▉",
+            "▉▉▉▉▉",
+            "     ",
+            "     ",
+        ];
+        let expected: String = rows.iter().map(|row| format!("{row}\n")).collect();
+        assert_eq!(steps[1].to_string(), expected);
+        assert_eq!(paper.steps(&[]).count(), 0);
+    }
+
+    #[test]
+    fn test_read() {
+        let (mut paper, instructions) = parse(EX);
+        for &fold in &instructions {
+            paper.fold(fold);
+        }
+        // The example folds to a square, which is not a letter.
+        let table = GlyphTable::default();
+        assert_eq!(
+            paper.read(&table),
+            Err(OcrError::Height {
+                height: 7,
+                expected: 6
+            })
+        );
+        let mut table = GlyphTable::new(5, 7);
+        let square = [
+            "#####", "#...#", "#...#", "#...#", "#####", ".....", ".....",
+        ];
+        table.insert('#', &square).unwrap();
+        assert_eq!(paper.read(&table), Ok("#".to_string()));
+        assert_eq!(
+            table.insert('x', &square[1..]),
+            Err(OcrError::GlyphSize {
+                letter: 'x',
+                width: 5,
+                height: 7
+            })
+        );
+
+        let coords = LETTERS.iter().enumerate().flat_map(|(index, (_, rows))| {
+            rows.iter().enumerate().flat_map(move |(y, row)| {
+                row.chars()
+                    .enumerate()
+                    .filter(|&(_, c)| c == '#')
+                    .map(move |(x, _)| (index as u64 * 5 + x as u64, y as u64))
+            })
+        });
+        let mut paper = Paper {
+            width: LETTERS.len() as u64 * 5 - 1,
+            height: 6,
+            coords: coords.collect(),
+            ..Default::default()
+        };
+        assert_eq!(
+            paper.read(&GlyphTable::default()).unwrap(),
+            "ABCEFGHIJKLOPRSUZ"
+        );
+        paper.coords.insert((8, 5));
+        assert_eq!(
+            paper.read(&GlyphTable::default()),
+            Err(OcrError::Unrecognised { index: 1 })
+        );
+    }
+
+    #[test]
+    fn test_export() {
+        let paper = Paper {
+            width: 5,
+            height: 2,
+            coords: HashSet::from([(0, 0), (4, 0), (2, 1)]),
+            ..Default::default()
+        };
+        let mut expected = b"P4\n5 2\n".to_vec();
+        expected.extend([0b1000_1000, 0b0010_0000]);
+        assert_eq!(paper.to_pbm(1), expected);
+        let mut expected = b"P4\n10 4\n".to_vec();
+        expected.extend([0b1100_0000, 0b1100_0000, 0b1100_0000, 0b1100_0000]);
+        expected.extend([0b0000_1100, 0b0000_0000, 0b0000_1100, 0b0000_0000]);
+        assert_eq!(paper.to_pbm(2), expected);
+
+        let expected = r#"<svg xmlns="http://www.w3.org/2000/svg" width="15" height="6">
+  <rect width="15" height="6" fill="white"/>
+  <rect x="0" y="0" width="3" height="3" fill="black"/>
+  <rect x="12" y="0" width="3" height="3" fill="black"/>
+  <rect x="6" y="3" width="3" height="3" fill="black"/>
+</svg>
+"#;
+        assert_eq!(paper.to_svg(3), expected);
+    }
+
+    #[test]
+    fn test_fold_off_midpoint() {
+        let paper = |width, coords: &[(u64, u64)]| Paper {
+            width,
+            height: 1,
+            coords: coords.iter().copied().collect(),
+            ..Default::default()
+        };
+
+        // The longer right part is mirrored over the left.
+        let mut folded = paper(10, &[(0, 0), (1, 0), (9, 0)]);
+        folded.fold(Fold::X(2));
+        assert_eq!((folded.width, folded.height), (7, 1));
+        assert_eq!(folded.coords, HashSet::from([(5, 0), (6, 0), (0, 0)]));
+
+        // The fold line is past the edge, so there is nothing to fold over.
+        let mut folded = paper(3, &[(0, 0), (2, 0)]);
+        folded.fold(Fold::X(5));
+        assert_eq!(folded.width, 5);
+        assert_eq!(folded.coords, HashSet::from([(0, 0), (2, 0)]));
+
+        // Dots on the fold line are lost.
+        let mut folded = paper(5, &[(2, 0), (3, 0)]);
+        folded.fold(Fold::X(2));
+        assert_eq!(folded.width, 2);
+        assert_eq!(folded.coords, HashSet::from([(1, 0)]));
+        folded.fold(Fold::Y(0));
+        assert_eq!((folded.width, folded.height), (2, 0));
+        assert!(folded.coords.is_empty());
+    }
+
+    #[test]
+    fn test_unfold() {
+        let (paper, instructions) = parse(EX);
+        let mut folded = paper.clone();
+        assert_eq!(folded.unfold(), None);
+        for &fold in &instructions {
+            folded.fold(fold);
+        }
+        assert_eq!(folded.coords.len(), 16);
+        assert_eq!(folded.folds().collect::<Vec<_>>(), instructions);
+
+        assert_eq!(folded.unfold(), Some(Fold::X(5)));
+        assert_eq!(folded.coords.len(), 17);
+        assert_eq!(folded.unfold(), Some(Fold::Y(7)));
+        assert_eq!(folded, paper);
+    }
 }
@@ -1,5 +1,12 @@
 const INPUT: &str = include_str!("./assets/day16.txt");
 
+use itertools::Itertools;
+use std::fmt;
+use thiserror::Error;
+
+pub use encode::{encode, encode_with, EncodeError};
+pub use parse::{parse, parse_with_max_depth, MAX_DEPTH};
+
 pub fn solve() -> String {
     let input = INPUT.lines().next().unwrap();
     let packets = parse::parse(input).unwrap();
@@ -10,16 +17,16 @@ pub fn solve() -> String {
 
 fn part1(mut packets: Vec<Packet>) -> usize {
     let mut total = 0;
-    while let Some(packet) = packets.pop() {
-        match packet {
-            Packet::Literal { version, value: _ } => total += version as usize,
+    while let Some(mut packet) = packets.pop() {
+        match &mut packet {
+            Packet::Literal { version, value: _ } => total += *version as usize,
             Packet::Operator {
                 version,
                 op: _,
                 packets: sub_packets,
             } => {
-                packets.extend(sub_packets.into_iter());
-                total += version as usize;
+                packets.append(sub_packets);
+                total += *version as usize;
             }
         }
     }
@@ -28,13 +35,21 @@ fn part1(mut packets: Vec<Packet>) -> usize {
 
 fn part2(packets: &[Packet]) -> usize {
     assert_eq!(packets.len(), 1);
-    packets[0].evaluate()
+    packets[0].evaluate().expect("Puzzle input is well-formed.")
 }
 
+// Parsing, `evaluate`, `validate` and dropping use explicit stacks, so they handle trees of
+// any depth. The derived traits, `simplify`, `encode` and serde recurse once per level, so
+// they are only safe up to around `MAX_DEPTH`.
 #[derive(Debug, Clone, PartialEq)]
-enum Packet {
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Packet {
     Literal {
         version: u8,
+        // Stored as little-endian bytes; serialised as a number when it fits in a u64,
+        // and as a decimal string otherwise. A value with high zero bytes is serialised as
+        // a map, `{"value": <number or string>, "bytes": <length>}`, to keep its length.
+        #[cfg_attr(feature = "serde", serde(with = "literal_serde"))]
         value: Vec<u8>,
     },
     Operator {
@@ -44,8 +59,25 @@ enum Packet {
     },
 }
 
+// Drops nested operators from an explicit stack, so that dropping a tree parsed with a large
+// maximum depth cannot overflow the call stack.
+impl Drop for Packet {
+    fn drop(&mut self) {
+        let mut stack = match self {
+            Packet::Operator { packets, .. } => std::mem::take(packets),
+            Packet::Literal { .. } => return,
+        };
+        while let Some(mut packet) = stack.pop() {
+            if let Packet::Operator { packets, .. } = &mut packet {
+                stack.append(packets);
+            }
+        }
+    }
+}
+
 #[derive(Copy, Clone, Debug, PartialEq)]
-enum Operation {
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Operation {
     Sum,
     Product,
     Min,
@@ -55,127 +87,677 @@ enum Operation {
     Equal,
 }
 
+// How an operator delimits its sub-packets.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum LengthType {
+    TotalLength,
+    Count,
+}
+
+impl Operation {
+    // The number of operands the operation accepts, as an inclusive (min, max) range.
+    pub fn arity(&self) -> (usize, Option<usize>) {
+        match self {
+            Operation::Sum | Operation::Product => (0, None),
+            Operation::Min | Operation::Max => (1, None),
+            Operation::Greater | Operation::Less | Operation::Equal => (2, Some(2)),
+        }
+    }
+
+    // Applies the operation to values whose arity has already been checked.
+    fn apply(&self, values: &[usize], location: &[usize]) -> Result<usize, PacketError> {
+        let overflow = || PacketError::Overflow {
+            location: Location(location.to_vec()),
+            op: *self,
+        };
+        let value = match self {
+            Operation::Sum => values
+                .iter()
+                .try_fold(0usize, |acc, &value| acc.checked_add(value))
+                .ok_or_else(overflow)?,
+            Operation::Product => values
+                .iter()
+                .try_fold(1usize, |acc, &value| acc.checked_mul(value))
+                .ok_or_else(overflow)?,
+            Operation::Min => *values.iter().min().expect("arity checked."),
+            Operation::Max => *values.iter().max().expect("arity checked."),
+            Operation::Greater => (values[0] > values[1]) as usize,
+            Operation::Less => (values[0] < values[1]) as usize,
+            Operation::Equal => (values[0] == values[1]) as usize,
+        };
+        Ok(value)
+    }
+
+    fn accepts(&self, operands: usize) -> bool {
+        let (min, max) = self.arity();
+        operands >= min && max.is_none_or(|max| operands <= max)
+    }
+}
+
+// Location of a packet in a tree, given as the index of the sub-packet taken at each level
+// starting from the root.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Location(pub Vec<usize>);
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.0.is_empty() {
+            return write!(f, "root");
+        }
+        write!(f, "root/{}", self.0.iter().join("/"))
+    }
+}
+
+#[derive(Clone, Debug, Error, PartialEq)]
+pub enum PacketError {
+    #[error(
+        "{op:?} operator at {location} expects {} operand(s), but {found} found.",
+        expected_operands(*.min, *.max)
+    )]
+    Arity {
+        location: Location,
+        op: Operation,
+        min: usize,
+        max: Option<usize>,
+        found: usize,
+    },
+    #[error("Literal at {location} is {bytes} bytes long, which does not fit in a usize.")]
+    LiteralTooLarge { location: Location, bytes: usize },
+    #[error("{op:?} operator at {location} overflowed.")]
+    Overflow { location: Location, op: Operation },
+}
+
+fn expected_operands(min: usize, max: Option<usize>) -> String {
+    match max {
+        Some(max) if min == max => format!("{min}"),
+        Some(max) => format!("{min} to {max}"),
+        None => format!("at least {min}"),
+    }
+}
+
 impl Packet {
-    fn evaluate(&self) -> usize {
+    // Checks that every operator in the tree has a valid number of operands and that every
+    // literal fits in a usize, reporting all violations found.
+    pub fn validate(&self) -> Result<(), Vec<PacketError>> {
+        let mut errors = Vec::new();
+        let mut stack = vec![(Vec::new(), self)];
+        while let Some((location, packet)) = stack.pop() {
+            if let Err(error) = packet.check(&location) {
+                errors.push(error);
+            }
+            if let Packet::Operator { packets, .. } = packet {
+                for (index, sub_packet) in packets.iter().enumerate().rev() {
+                    let mut location = location.clone();
+                    location.push(index);
+                    stack.push((location, sub_packet));
+                }
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    // Evaluates the tree using an explicit stack, so that deeply nested packets cannot
+    // overflow the call stack.
+    pub fn evaluate(&self) -> Result<usize, PacketError> {
+        // Each operator being evaluated is kept alongside the values of its sub-packets so
+        // far, while `location` holds the index of the packet being visited at each level.
+        let mut stack: Vec<(Operation, &[Packet], Vec<usize>)> = Vec::new();
+        let mut location = Vec::new();
+        let mut next = self;
+        loop {
+            next.check(&location)?;
+            let mut value = match next {
+                Packet::Literal { version: _, value } => bytes_to_usize(value),
+                Packet::Operator {
+                    version: _,
+                    op,
+                    packets,
+                } => match packets.first() {
+                    Some(first) => {
+                        stack.push((*op, packets, Vec::with_capacity(packets.len())));
+                        location.push(0);
+                        next = first;
+                        continue;
+                    }
+                    None => op.apply(&[], &location)?,
+                },
+            };
+            loop {
+                let (_, packets, values) = match stack.last_mut() {
+                    Some(frame) => frame,
+                    None => return Ok(value),
+                };
+                values.push(value);
+                if let Some(sibling) = packets.get(values.len()) {
+                    *location.last_mut().expect("location of sibling.") = values.len();
+                    next = sibling;
+                    break;
+                }
+                let (op, _, values) = stack.pop().expect("frame on stack.");
+                location.pop();
+                value = op.apply(&values, &location)?;
+            }
+        }
+    }
+
+    // Checks the packet itself, ignoring its sub-packets.
+    fn check(&self, location: &[usize]) -> Result<(), PacketError> {
         match self {
-            Packet::Literal { version: _, value } => bytes_to_usize(value),
+            Packet::Literal { version: _, value } => {
+                let bytes = value
+                    .iter()
+                    .rposition(|&byte| byte != 0)
+                    .map_or(0, |pos| pos + 1);
+                if bytes > std::mem::size_of::<usize>() {
+                    return Err(PacketError::LiteralTooLarge {
+                        location: Location(location.to_vec()),
+                        bytes,
+                    });
+                }
+            }
             Packet::Operator {
                 version: _,
                 op,
                 packets,
             } => {
-                let values: Vec<_> = packets.iter().map(|packet| packet.evaluate()).collect();
-                match op {
-                    Operation::Sum => values.iter().sum(),
-                    Operation::Product => values.iter().product(),
-                    Operation::Min => *values.iter().min().unwrap(),
-                    Operation::Max => *values.iter().max().unwrap(),
-                    Operation::Greater => {
-                        debug_assert_eq!(values.len(), 2);
-                        if values[0] > values[1] {
-                            1
-                        } else {
-                            0
-                        }
-                    }
-                    Operation::Less => {
-                        debug_assert_eq!(values.len(), 2);
-                        if values[0] < values[1] {
-                            1
-                        } else {
-                            0
-                        }
+                if !op.accepts(packets.len()) {
+                    let (min, max) = op.arity();
+                    return Err(PacketError::Arity {
+                        location: Location(location.to_vec()),
+                        op: *op,
+                        min,
+                        max,
+                        found: packets.len(),
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Packet {
+    // Produces a smaller tree that evaluates to the same value. Nested associative operators
+    // are flattened, identity operands removed, single-operand operators replaced by their
+    // operand, and comparisons between literals folded into a literal. Invalid operators are
+    // left in place so that `validate` can still report them, as are any whose operands
+    // could not be spliced into their parent without changing its result.
+    pub fn simplify(mut self) -> Packet {
+        match &mut self {
+            Packet::Literal { .. } => self,
+            Packet::Operator {
+                version,
+                op,
+                packets,
+            } => {
+                let (version, op) = (*version, *op);
+                let packets = std::mem::take(packets);
+                let mut operands = Vec::with_capacity(packets.len());
+                for mut packet in packets.into_iter().map(Packet::simplify) {
+                    if !packet.splices_into(op) {
+                        operands.push(packet);
+                    } else if let Packet::Operator { packets, .. } = &mut packet {
+                        operands.append(packets);
                     }
-                    Operation::Equal => {
-                        debug_assert_eq!(values.len(), 2);
-                        if values[0] == values[1] {
-                            1
-                        } else {
-                            0
-                        }
+                }
+                if let Some(identity) = op.identity() {
+                    operands.retain(|packet| packet.constant() != Some(identity));
+                    if operands.is_empty() {
+                        return Packet::literal(version, identity);
                     }
                 }
+                if operands.len() == 1 && op.is_associative() {
+                    return operands.pop().expect("one operand.");
+                }
+                let folded = Packet::Operator {
+                    version,
+                    op,
+                    packets: operands,
+                };
+                match folded.fold() {
+                    Some(value) => Packet::literal(version, value),
+                    None => folded,
+                }
+            }
+        }
+    }
+
+    // Whether this is an operator of the same associative operation as `op`, whose operands
+    // can replace it in an `op` operator without changing what that evaluates to. It must
+    // evaluate without error, and a product must not be zero, as a zero operand only
+    // prevents overflow in the products that come after it.
+    fn splices_into(&self, op: Operation) -> bool {
+        match self {
+            Packet::Operator {
+                op: inner_op,
+                packets,
+                ..
+            } if *inner_op == op && op.is_associative() && op.accepts(packets.len()) => {
+                match self.evaluate() {
+                    Ok(0) => op != Operation::Product,
+                    Ok(_) => true,
+                    Err(_) => false,
+                }
             }
+            _ => false,
+        }
+    }
+
+    fn literal(version: u8, value: usize) -> Packet {
+        let value = value.to_le_bytes();
+        let len = value
+            .iter()
+            .rposition(|&byte| byte != 0)
+            .map_or(1, |pos| pos + 1);
+        Packet::Literal {
+            version,
+            value: value[..len].to_vec(),
+        }
+    }
+
+    // The value of a literal, if it fits in a usize.
+    fn constant(&self) -> Option<usize> {
+        match self {
+            Packet::Literal { .. } => self.evaluate().ok(),
+            Packet::Operator { .. } => None,
+        }
+    }
+
+    // The value of a comparison whose operands are both literals.
+    fn fold(&self) -> Option<usize> {
+        match self {
+            Packet::Operator { op, packets, .. }
+                if !op.is_associative() && packets.iter().all(|p| p.constant().is_some()) =>
+            {
+                self.evaluate().ok()
+            }
+            _ => None,
         }
     }
 }
 
+impl Operation {
+    fn is_associative(&self) -> bool {
+        matches!(
+            self,
+            Operation::Sum | Operation::Product | Operation::Min | Operation::Max
+        )
+    }
+
+    fn identity(&self) -> Option<usize> {
+        match self {
+            Operation::Sum => Some(0),
+            Operation::Product => Some(1),
+            _ => None,
+        }
+    }
+}
+
+// Callers are expected to have checked that the significant bytes fit in a usize.
 fn bytes_to_usize(input: &[u8]) -> usize {
-    debug_assert!(input.len() <= 8);
     let mut bytes = [0; 8];
-    let (first, _) = bytes.split_at_mut(input.len());
-    first.copy_from_slice(input);
+    for (byte, &value) in bytes.iter_mut().zip(input) {
+        *byte = value;
+    }
     usize::from_le_bytes(bytes)
 }
 
+#[cfg(feature = "serde")]
+mod literal_serde {
+    use serde::de::{self, MapAccess, Visitor};
+    use serde::ser::SerializeMap;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use std::fmt;
+
+    // The value without its byte count, as a number or a decimal string.
+    struct Magnitude<'v>(&'v [u8]);
+
+    impl Serialize for Magnitude<'_> {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            let value = self.0;
+            if value.len() <= 8 {
+                let mut bytes = [0; 8];
+                bytes[..value.len()].copy_from_slice(value);
+                serializer.serialize_u64(u64::from_le_bytes(bytes))
+            } else {
+                serializer.serialize_str(&to_decimal(value))
+            }
+        }
+    }
+
+    // Values with high zero bytes, which parsing gives literals with leading zero groups, keep
+    // their length so that they encode to the same groups again.
+    pub(super) fn serialize<S: Serializer>(value: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        let magnitude = Magnitude(trimmed(value));
+        if magnitude.0.len() == value.len() && !value.is_empty() {
+            return magnitude.serialize(serializer);
+        }
+        let mut map = serializer.serialize_map(Some(2))?;
+        map.serialize_entry("value", &magnitude)?;
+        map.serialize_entry("bytes", &value.len())?;
+        map.end()
+    }
+
+    pub(super) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<u8>, D::Error> {
+        deserializer.deserialize_any(LiteralVisitor)
+    }
+
+    // The longest value a map may pad to, far beyond any transmission, so that a document
+    // cannot ask for an arbitrarily large allocation.
+    const MAX_BYTES: usize = 1 << 16;
+
+    // The "value" entry of a map, which must be a number or a decimal string.
+    struct Owned(Vec<u8>);
+
+    impl<'de> Deserialize<'de> for Owned {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Owned, D::Error> {
+            deserializer.deserialize_any(MagnitudeVisitor).map(Owned)
+        }
+    }
+
+    struct MagnitudeVisitor;
+
+    impl<'de> Visitor<'de> for MagnitudeVisitor {
+        type Value = Vec<u8>;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("an unsigned integer or a string of decimal digits")
+        }
+
+        fn visit_u64<E: de::Error>(self, value: u64) -> Result<Vec<u8>, E> {
+            Ok(trimmed(&value.to_le_bytes()).to_vec())
+        }
+
+        fn visit_str<E: de::Error>(self, value: &str) -> Result<Vec<u8>, E> {
+            from_decimal(value).ok_or_else(|| E::invalid_value(de::Unexpected::Str(value), &self))
+        }
+    }
+
+    struct LiteralVisitor;
+
+    impl<'de> Visitor<'de> for LiteralVisitor {
+        type Value = Vec<u8>;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str(
+                "an unsigned integer, a string of decimal digits, or a map of one to its bytes",
+            )
+        }
+
+        fn visit_u64<E: de::Error>(self, value: u64) -> Result<Vec<u8>, E> {
+            MagnitudeVisitor.visit_u64(value)
+        }
+
+        fn visit_str<E: de::Error>(self, value: &str) -> Result<Vec<u8>, E> {
+            MagnitudeVisitor.visit_str(value)
+        }
+
+        fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Vec<u8>, A::Error> {
+            let mut value: Option<Owned> = None;
+            let mut bytes: Option<usize> = None;
+            while let Some(key) = map.next_key::<String>()? {
+                match key.as_str() {
+                    "value" => value = Some(map.next_value()?),
+                    "bytes" => bytes = Some(map.next_value()?),
+                    _ => return Err(de::Error::unknown_field(&key, &["value", "bytes"])),
+                }
+            }
+            let mut value = value.ok_or_else(|| de::Error::missing_field("value"))?.0;
+            let bytes = bytes.ok_or_else(|| de::Error::missing_field("bytes"))?;
+            if bytes > MAX_BYTES {
+                return Err(de::Error::invalid_value(
+                    de::Unexpected::Unsigned(bytes as u64),
+                    &"a length of at most 65536 bytes",
+                ));
+            }
+            if value[bytes.min(value.len())..]
+                .iter()
+                .any(|&byte| byte != 0)
+            {
+                return Err(de::Error::invalid_length(
+                    bytes,
+                    &"enough bytes for the value",
+                ));
+            }
+            value.resize(bytes, 0);
+            Ok(value)
+        }
+    }
+
+    // Strips the high-order zero bytes, always leaving at least one byte.
+    fn trimmed(value: &[u8]) -> &[u8] {
+        let len = value
+            .iter()
+            .rposition(|&byte| byte != 0)
+            .map_or(1, |pos| pos + 1);
+        &value[..len.min(value.len())]
+    }
+
+    // Repeatedly divides the little-endian value by ten, collecting the remainders.
+    fn to_decimal(value: &[u8]) -> String {
+        let mut value = value.to_vec();
+        let mut digits = Vec::new();
+        loop {
+            let mut remainder = 0u16;
+            for byte in value.iter_mut().rev() {
+                let current = remainder << 8 | *byte as u16;
+                *byte = (current / 10) as u8;
+                remainder = current % 10;
+            }
+            digits.push(b'0' + remainder as u8);
+            if value.iter().all(|&byte| byte == 0) {
+                break;
+            }
+        }
+        digits.reverse();
+        String::from_utf8(digits).expect("digits are ASCII.")
+    }
+
+    fn from_decimal(input: &str) -> Option<Vec<u8>> {
+        if input.is_empty() {
+            return None;
+        }
+        let mut value = vec![0u8];
+        for digit in input.chars() {
+            let mut carry = digit.to_digit(10)? as u16;
+            for byte in value.iter_mut() {
+                let current = *byte as u16 * 10 + carry;
+                *byte = current as u8;
+                carry = current >> 8;
+            }
+            if carry > 0 {
+                value.push(carry as u8);
+            }
+        }
+        Some(trimmed(&value).to_vec())
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn test_decimal_round_trip() {
+            assert_eq!(to_decimal(&[0]), "0");
+            assert_eq!(to_decimal(&[0xE5, 0x07]), "2021");
+            assert_eq!(from_decimal("2021"), Some(vec![0xE5, 0x07]));
+            assert_eq!(from_decimal("0"), Some(vec![0]));
+            assert_eq!(from_decimal(""), None);
+            assert_eq!(from_decimal("12a"), None);
+            let big = "340282366920938463463374607431768211455";
+            assert_eq!(from_decimal(big), Some(vec![0xFF; 16]));
+            assert_eq!(to_decimal(&[0xFF; 16]), big);
+        }
+    }
+}
+
 mod parse {
     use super::{Operation, Packet};
     use crate::bits::Bits;
     use anyhow::{bail, Context, Result};
     use itertools::Itertools;
 
-    pub(super) fn parse(input: &str) -> Result<Vec<Packet>> {
-        let bytes: Vec<_> = (0..input.len())
-            .step_by(2)
-            .map(|index| u8::from_str_radix(&input[index..index + 2], 16).unwrap())
-            .collect();
-        let bits = Bits::new(&bytes);
-        packets(bits)
+    // Maximum nesting of operators accepted by `parse`.
+    pub const MAX_DEPTH: usize = 1024;
+
+    pub fn parse(input: &str) -> Result<Vec<Packet>> {
+        parse_with_max_depth(input, MAX_DEPTH)
     }
 
-    fn packets(mut input: Bits) -> Result<Vec<Packet>> {
-        let mut packets = Vec::new();
-        while let Ok((temp_input, packet)) = packet(input) {
-            input = temp_input;
-            packets.push(packet);
+    // Parses the transmission using an explicit stack rather than recursion, failing if
+    // operators are nested more than `max_depth` deep. Deeper trees than `MAX_DEPTH` can
+    // be evaluated and dropped, but not cloned, compared, printed, simplified or encoded.
+    pub fn parse_with_max_depth(input: &str, max_depth: usize) -> Result<Vec<Packet>> {
+        let mut buffer = vec![0; input.len().div_ceil(2)];
+        let bits = Bits::from_hex(input, &mut buffer)?;
+        packets(bits, max_depth)
+    }
+
+    // How the sub-packets of an operator being parsed are delimited.
+    enum Delimiter<'i> {
+        // The sub-packets are read from a reader bounded to their total length, and `rest`
+        // is the input following them.
+        Length { rest: Bits<'i> },
+        Count(usize),
+    }
+
+    // An operator whose sub-packets are still being parsed.
+    struct Frame<'i> {
+        version: u8,
+        op: Operation,
+        delimiter: Delimiter<'i>,
+        packets: Vec<Packet>,
+    }
+
+    impl<'i> Frame<'i> {
+        fn is_complete(&self, input: Bits) -> bool {
+            match self.delimiter {
+                Delimiter::Length { rest: _ } => input.is_empty(),
+                Delimiter::Count(count) => self.packets.len() == count,
+            }
+        }
+
+        // Returns the input following the operator, skipping any padding left in a length
+        // delimited operator.
+        fn finish(self, input: Bits<'i>) -> (Bits<'i>, Packet) {
+            let input = match self.delimiter {
+                Delimiter::Length { rest } => rest,
+                Delimiter::Count(_) => input,
+            };
+            let packet = Packet::Operator {
+                version: self.version,
+                op: self.op,
+                packets: self.packets,
+            };
+            (input, packet)
         }
-        Ok(packets)
     }
 
-    fn packet(input: Bits) -> Result<(Bits, Packet)> {
-        let res = literal(input)
-            .or_else(|_| operator(input))
-            .context("Packet not found.");
-        res
+    // Packets are parsed until the input is exhausted. A sub-packet that fails to parse ends a
+    // length delimited operator early, with the remainder of its length treated as padding,
+    // whereas it is an error for a count delimited operator, which then fails in turn.
+    fn packets(mut input: Bits, max_depth: usize) -> Result<Vec<Packet>> {
+        let mut packets = Vec::new();
+        let mut stack: Vec<Frame> = Vec::new();
+        loop {
+            // Parse the next packet, or open a new operator and continue with its sub-packets.
+            let mut next = match header(input) {
+                Ok((rest, Header::Literal(packet))) => Ok((rest, packet)),
+                Ok((rest, Header::Operator(frame))) => {
+                    if stack.len() >= max_depth {
+                        bail!("Packets are nested more than {max_depth} deep.");
+                    }
+                    input = rest;
+                    if !frame.is_complete(input) {
+                        stack.push(frame);
+                        continue;
+                    }
+                    Ok(frame.finish(input))
+                }
+                Err(error) => Err(error),
+            };
+
+            // Hand the result up the stack, closing operators as they complete or fail.
+            loop {
+                let frame = match stack.last_mut() {
+                    Some(frame) => frame,
+                    None => match next {
+                        Ok((rest, packet)) => {
+                            input = rest;
+                            packets.push(packet);
+                            break;
+                        }
+                        Err(_) => return Ok(packets),
+                    },
+                };
+                match (next, &frame.delimiter) {
+                    (Ok((rest, packet)), _) => {
+                        input = rest;
+                        frame.packets.push(packet);
+                        if !frame.is_complete(input) {
+                            break;
+                        }
+                    }
+                    (Err(_), Delimiter::Length { .. }) => (),
+                    (Err(error), Delimiter::Count(count)) => {
+                        let sub_packet_count = frame.packets.len();
+                        let count = *count;
+                        stack.pop();
+                        next = Err(error.context(format!(
+                            "{count} packet(s) expected, but {sub_packet_count} packet(s) found."
+                        )));
+                        continue;
+                    }
+                }
+                let frame = stack.pop().expect("frame on stack.");
+                next = Ok(frame.finish(input));
+            }
+        }
     }
 
-    fn literal(input: Bits) -> Result<(Bits, Packet)> {
-        let (input, version) = version_tag(input).context("Literal not found.")?;
-        let input = literal_type(input).context("Literal not found.")?;
-        let (input, value) = literal_value(input).context("Literal not found.")?;
-        Ok((input, Packet::Literal { version, value }))
+    enum Header<'i> {
+        Literal(Packet),
+        Operator(Frame<'i>),
     }
 
-    fn operator(input: Bits) -> Result<(Bits, Packet)> {
-        let (input, version) = version_tag(input).context("Operator not found.")?;
-        let (input, variant) = operator_type(input).context("Operator not found.")?;
-        let (input, packets) = total_length_op_data(input)
-            .or_else(|_| count_op_data(input))
-            .context("Operator sub-packets not found.")?;
-        Ok((
-            input,
-            Packet::Operator {
-                version,
-                op: variant,
-                packets,
-            },
-        ))
+    // Parses a literal in full, or the header of an operator up to its sub-packets.
+    fn header(input: Bits) -> Result<(Bits, Header)> {
+        let (input, version) = version_tag(input).context("Packet not found.")?;
+        if let Ok(input) = literal_type(input) {
+            let (input, value) = literal_value(input).context("Literal not found.")?;
+            return Ok((input, Header::Literal(Packet::Literal { version, value })));
+        }
+        let (input, op) = operator_type(input).context("Operator not found.")?;
+        let (input, delimiter) = length_type(input).context("Operator sub-packets not found.")?;
+        let frame = Frame {
+            version,
+            op,
+            delimiter,
+            packets: Vec::new(),
+        };
+        Ok((input, Header::Operator(frame)))
     }
 
     fn version_tag(mut input: Bits) -> Result<(Bits, u8)> {
-        let tag = input.take(3).context("Version tag not found.")?[0];
+        let tag = input.take_u8(3).context("Version tag not found.")?;
         Ok((input, tag))
     }
 
     fn literal_type(mut input: Bits) -> Result<Bits> {
-        match input.take(3).context("Literal tag not found.")?[0] {
+        match input.take_u8(3).context("Literal tag not found.")? {
             4 => Ok(input),
             tag => bail!("Literal tag not found. '{tag}' found instead."),
         }
     }
 
     fn operator_type(mut input: Bits) -> Result<(Bits, Operation)> {
-        let tag = match input.take(3).context("Operator tag not found.")?[0] {
+        let tag = match input.take_u8(3).context("Operator tag not found.")? {
             4 => bail!("Operator tag not found. Literal tag found instead."),
             0 => Operation::Sum,
             1 => Operation::Product,
@@ -194,8 +776,8 @@ mod parse {
     fn literal_value(mut input: Bits) -> Result<(Bits, Vec<u8>)> {
         let mut partial_bytes = Vec::new();
         loop {
-            let tag = input.take(1).context("Literal value not found.")?[0];
-            let data = input.take(4).context("Literal value not found.")?[0];
+            let tag = input.take_u8(1).context("Literal value not found.")?;
+            let data = input.take_u8(4).context("Literal value not found.")?;
             partial_bytes.push(data);
             if tag == 0 {
                 break;
@@ -216,69 +798,18 @@ mod parse {
         Ok((input, bytes))
     }
 
-    fn total_length_op_data(mut input: Bits) -> Result<(Bits, Vec<Packet>)> {
-        use std::convert::TryInto;
-        let tag = input.take(1).context("Length tag not found.")?[0];
-        if tag != 0 {
-            bail!("Length tag not found.")
-        }
-        let length: [u8; 2] = input
-            .take(15)
-            .context("Length tag not found.")?
-            .try_into()
-            .expect("take(15) returns 2 bytes.");
-        let length = u16::from_be_bytes(length) as usize;
-
-        // Counts the difference in length between the two Bits in bits
-        fn bit_count(start: Bits, end: Bits) -> usize {
-            let (start_len, start_offset) = start.len();
-            let (end_len, end_offset) = end.len();
-            let total_bits_diff = (start_len - end_len) * 8;
-            total_bits_diff - start_offset as usize + end_offset as usize
-        }
-
-        let mut running_input = input;
-        let mut sub_packets = Vec::new();
-        while let Ok((temp_input, packet)) = packet(running_input) {
-            let sub_packets_bits = bit_count(input, temp_input);
-            if sub_packets_bits > length {
-                break;
-            } else {
-                running_input = temp_input;
-                sub_packets.push(packet);
-            }
-        }
-
-        let padding_bit_count = length - bit_count(input, running_input);
-        let _ = running_input
-            .take(padding_bit_count)
-            .with_context(|| format!("{padding_bit_count} bit(s) expected as padding."))?;
-
-        Ok((running_input, sub_packets))
-    }
-
-    fn count_op_data(mut input: Bits) -> Result<(Bits, Vec<Packet>)> {
-        use std::convert::TryInto;
-        let tag = input.take(1).context("Count tag not found.")?[0];
-        if tag != 1 {
-            bail!("Count tag not found.")
+    fn length_type(mut input: Bits) -> Result<(Bits, Delimiter)> {
+        let tag = input.take_u8(1).context("Length type not found.")?;
+        if tag == 0 {
+            let length = input.take_u16(15).context("Length tag not found.")? as usize;
+            let sub_packets = input
+                .split(length)
+                .with_context(|| format!("{length} bit(s) of sub-packets expected."))?;
+            Ok((sub_packets, Delimiter::Length { rest: input }))
+        } else {
+            let count = input.take_u16(11).context("Count value not found.")? as usize;
+            Ok((input, Delimiter::Count(count)))
         }
-        let count: [u8; 2] = input
-            .take(11)
-            .context("Count value not found.")?
-            .try_into()
-            .expect("take(11) returns 2 bytes.");
-        let count = u16::from_be_bytes(count);
-        let mut sub_packets = Vec::with_capacity(count as usize);
-        while count as usize - sub_packets.len() > 0 {
-            let (temp_input, packet) = packet(input).with_context(|| {
-                let sub_packet_count = sub_packets.len();
-                format!("{count} packet(s) expected, but {sub_packet_count} packet(s) found.")
-            })?;
-            sub_packets.push(packet);
-            input = temp_input;
-        }
-        Ok((input, sub_packets))
     }
 
     #[cfg(test)]
@@ -288,10 +819,10 @@ mod parse {
         fn test_version_tag() {
             let bits = Bits::new(&[0b11100011]);
             let (bits, tag) = version_tag(bits).unwrap();
-            assert_eq!(bits.len(), (1, 3));
+            assert_eq!(bits.position(), 3);
             assert_eq!(tag, 0b00000111);
             let (bits, tag) = version_tag(bits).unwrap();
-            assert_eq!(bits.len(), (1, 6));
+            assert_eq!(bits.position(), 6);
             assert_eq!(tag, 0b00000000);
             assert!(version_tag(bits).is_err());
         }
@@ -309,10 +840,162 @@ mod parse {
         }
     }
 }
+mod encode {
+    use super::{LengthType, Location, Operation, Packet};
+    use crate::bits::BitWriter;
+    use thiserror::Error;
+
+    #[derive(Clone, Debug, Error, PartialEq)]
+    pub enum EncodeError {
+        #[error("Version {version} at {location} does not fit in 3 bits.")]
+        Version { location: Location, version: u8 },
+        #[error("{count} sub-packets at {location} do not fit in the 11 bit count.")]
+        TooManyPackets { location: Location, count: usize },
+        #[error("{bits} bits of sub-packets at {location} do not fit in the 15 bit length.")]
+        TooLong { location: Location, bits: usize },
+    }
+
+    // Encodes the packet as a hex transmission, using the count length type for every operator.
+    pub fn encode(packet: &Packet) -> Result<String, EncodeError> {
+        encode_with(packet, |_| LengthType::Count)
+    }
+
+    // Encodes the packet as a hex transmission, calling `length_type` for each operator, in
+    // depth-first order, to choose how its sub-packets are delimited.
+    pub fn encode_with<F>(packet: &Packet, mut length_type: F) -> Result<String, EncodeError>
+    where
+        F: FnMut(&Packet) -> LengthType,
+    {
+        let mut bits = BitWriter::new();
+        push_packet(&mut bits, packet, &mut Vec::new(), &mut length_type)?;
+        let hex = bits
+            .into_bytes()
+            .iter()
+            .map(|byte| format!("{byte:02X}"))
+            .collect();
+        Ok(hex)
+    }
+
+    fn push_packet<F>(
+        bits: &mut BitWriter,
+        packet: &Packet,
+        location: &mut Vec<usize>,
+        length_type: &mut F,
+    ) -> Result<(), EncodeError>
+    where
+        F: FnMut(&Packet) -> LengthType,
+    {
+        match packet {
+            Packet::Literal { version, value } => {
+                push_version(bits, *version, location)?;
+                push_uint(bits, 4, 3);
+                push_literal_value(bits, value);
+            }
+            Packet::Operator {
+                version,
+                op,
+                packets,
+            } => {
+                push_version(bits, *version, location)?;
+                push_uint(bits, op_type(*op), 3);
+                match length_type(packet) {
+                    LengthType::TotalLength => {
+                        let mut sub_bits = BitWriter::new();
+                        for (index, sub_packet) in packets.iter().enumerate() {
+                            location.push(index);
+                            push_packet(&mut sub_bits, sub_packet, location, length_type)?;
+                            location.pop();
+                        }
+                        if sub_bits.len() >= 1 << 15 {
+                            return Err(EncodeError::TooLong {
+                                location: Location(location.clone()),
+                                bits: sub_bits.len(),
+                            });
+                        }
+                        push_uint(bits, 0, 1);
+                        push_uint(bits, sub_bits.len() as u64, 15);
+                        bits.append(&sub_bits);
+                    }
+                    LengthType::Count => {
+                        if packets.len() >= 1 << 11 {
+                            return Err(EncodeError::TooManyPackets {
+                                location: Location(location.clone()),
+                                count: packets.len(),
+                            });
+                        }
+                        push_uint(bits, 1, 1);
+                        push_uint(bits, packets.len() as u64, 11);
+                        for (index, sub_packet) in packets.iter().enumerate() {
+                            location.push(index);
+                            push_packet(bits, sub_packet, location, length_type)?;
+                            location.pop();
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn push_version(
+        bits: &mut BitWriter,
+        version: u8,
+        location: &[usize],
+    ) -> Result<(), EncodeError> {
+        if version >= 8 {
+            return Err(EncodeError::Version {
+                location: Location(location.to_vec()),
+                version,
+            });
+        }
+        push_uint(bits, version as u64, 3);
+        Ok(())
+    }
+
+    fn push_uint(bits: &mut BitWriter, value: u64, width: usize) {
+        bits.write_uint(value, width).expect("width fits in a u64.");
+    }
+
+    // Writes the little-endian value as 4 bit groups, most significant first, each preceded by
+    // a continuation bit. The number of groups is chosen so that parsing restores the same
+    // number of bytes.
+    fn push_literal_value(bits: &mut BitWriter, value: &[u8]) {
+        let mut nibbles: Vec<u8> = value
+            .iter()
+            .rev()
+            .flat_map(|byte| [byte >> 4, byte & 0xF])
+            .collect();
+        if nibbles.len() > 1 && nibbles[0] == 0 {
+            nibbles.remove(0);
+        }
+        if nibbles.is_empty() {
+            nibbles.push(0);
+        }
+        let last = nibbles.len() - 1;
+        for (index, nibble) in nibbles.into_iter().enumerate() {
+            push_uint(bits, (index != last) as u64, 1);
+            push_uint(bits, nibble as u64, 4);
+        }
+    }
+
+    fn op_type(op: Operation) -> u64 {
+        match op {
+            Operation::Sum => 0,
+            Operation::Product => 1,
+            Operation::Min => 2,
+            Operation::Max => 3,
+            Operation::Greater => 5,
+            Operation::Less => 6,
+            Operation::Equal => 7,
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::parse::parse;
     use super::*;
+    use proptest::prelude::*;
 
     macro_rules! assert_literal {
         ($expr:expr, |$version:pat, $value:pat| $test:block) => {
@@ -342,6 +1025,19 @@ mod tests {
         };
     }
 
+    // Shorthands for building trees of version 0 packets.
+    fn literal(value: usize) -> Packet {
+        Packet::literal(0, value)
+    }
+
+    fn operator(op: Operation, packets: Vec<Packet>) -> Packet {
+        Packet::Operator {
+            version: 0,
+            op,
+            packets,
+        }
+    }
+
     #[test]
     fn test_example_1() {
         let actual = parse("D2FE28").unwrap();
@@ -349,6 +1045,7 @@ mod tests {
             assert_eq!(*value, 2021u16.to_le_bytes().to_vec());
         });
     }
+
     #[test]
     fn test_example_2() {
         let actual = parse("38006F45291200").unwrap();
@@ -361,6 +1058,7 @@ mod tests {
             });
         })
     }
+
     #[test]
     fn test_example_3() {
         let actual = parse("EE00D40C823060").unwrap();
@@ -376,6 +1074,7 @@ mod tests {
             })
         })
     }
+
     #[test]
     fn test_final_examples() {
         assert_eq!(part1(parse("8A004A801A8002F478").unwrap()), 16);
@@ -391,4 +1090,373 @@ mod tests {
         assert_eq!(part2(&parse("9C005AC2F8F0").unwrap()), 0);
         assert_eq!(part2(&parse("9C0141080250320F1802104A08").unwrap()), 1);
     }
+
+    #[test]
+    fn test_validate() {
+        let tree = operator(
+            Operation::Sum,
+            vec![
+                literal(1),
+                operator(Operation::Less, vec![literal(1)]),
+                operator(Operation::Max, vec![]),
+            ],
+        );
+        assert_eq!(
+            tree.validate(),
+            Err(vec![
+                PacketError::Arity {
+                    location: Location(vec![1]),
+                    op: Operation::Less,
+                    min: 2,
+                    max: Some(2),
+                    found: 1,
+                },
+                PacketError::Arity {
+                    location: Location(vec![2]),
+                    op: Operation::Max,
+                    min: 1,
+                    max: None,
+                    found: 0,
+                },
+            ])
+        );
+        assert_eq!(
+            tree.evaluate().unwrap_err().to_string(),
+            "Less operator at root/1 expects 2 operand(s), but 1 found."
+        );
+        assert_eq!(
+            operator(Operation::Max, vec![])
+                .evaluate()
+                .unwrap_err()
+                .to_string(),
+            "Max operator at root expects at least 1 operand(s), but 0 found."
+        );
+        let huge = Packet::Literal {
+            version: 0,
+            value: vec![1; 9],
+        };
+        assert!(matches!(
+            huge.evaluate(),
+            Err(PacketError::LiteralTooLarge { bytes: 9, .. })
+        ));
+        let overflow = operator(
+            Operation::Product,
+            vec![
+                Packet::Literal {
+                    version: 0,
+                    value: vec![0xFF; 8],
+                },
+                literal(2),
+            ],
+        );
+        assert!(overflow.validate().is_ok());
+        assert!(matches!(
+            overflow.evaluate(),
+            Err(PacketError::Overflow {
+                op: Operation::Product,
+                ..
+            })
+        ));
+        for hex in ["C200B40A82", "9C0141080250320F1802104A08"] {
+            assert!(parse(hex).unwrap()[0].validate().is_ok());
+        }
+    }
+
+    #[test]
+    fn test_simplify() {
+        // Nested sums are flattened and zero operands dropped.
+        let tree = operator(
+            Operation::Sum,
+            vec![
+                literal(1),
+                operator(Operation::Sum, vec![literal(0), literal(2), literal(3)]),
+            ],
+        );
+        assert_eq!(
+            tree.clone().simplify(),
+            operator(Operation::Sum, vec![literal(1), literal(2), literal(3)])
+        );
+        assert_eq!(tree.clone().simplify().evaluate(), tree.evaluate());
+
+        // Constant comparisons fold into a literal, which may then be an identity operand.
+        let tree = operator(
+            Operation::Product,
+            vec![
+                operator(Operation::Equal, vec![literal(3), literal(3)]),
+                operator(Operation::Max, vec![literal(4), literal(5)]),
+            ],
+        );
+        assert_eq!(
+            tree.simplify(),
+            operator(Operation::Max, vec![literal(4), literal(5)])
+        );
+
+        // Single-operand operators are replaced by their operand.
+        let wrapped = operator(
+            Operation::Product,
+            vec![operator(Operation::Min, vec![literal(7)])],
+        );
+        assert_eq!(wrapped.simplify(), literal(7));
+        assert_eq!(operator(Operation::Sum, vec![]).simplify(), literal(0));
+
+        // Invalid operators are left for `validate` to report.
+        let invalid = operator(Operation::Greater, vec![literal(1)]);
+        assert_eq!(invalid.clone().simplify(), invalid);
+        // Including inside an operator they would otherwise be flattened into.
+        let tree = operator(
+            Operation::Min,
+            vec![operator(Operation::Min, vec![]), literal(3)],
+        );
+        assert_eq!(tree.clone().simplify(), tree);
+
+        // A product that overflows stays whole, rather than being multiplied by zero first.
+        let overflowing = operator(Operation::Product, vec![literal(usize::MAX), literal(2)]);
+        let tree = operator(Operation::Product, vec![literal(0), overflowing]);
+        assert_eq!(tree.clone().simplify(), tree);
+        // As does a zero product, which could let a later operand overflow.
+        let zero = operator(Operation::Product, vec![literal(2), literal(0)]);
+        let tree = operator(Operation::Product, vec![literal(usize::MAX), zero]);
+        assert_eq!(tree.clone().simplify(), tree);
+        assert_eq!(tree.evaluate(), Ok(0));
+
+        for hex in [
+            "C200B40A82",
+            "04005AC33890",
+            "880086C3E88112",
+            "CE00C43D881120",
+            "D8005AC2A8F0",
+            "9C0141080250320F1802104A08",
+        ] {
+            let packet = parse(hex).unwrap().pop().unwrap();
+            assert_eq!(packet.clone().simplify().evaluate(), packet.evaluate());
+        }
+    }
+
+    #[test]
+    fn test_encode() {
+        for hex in ["D2FE28", "38006F45291200", "EE00D40C823060"] {
+            let packet = parse(hex).unwrap().pop().unwrap();
+            let length_type = |packet: &Packet| match packet {
+                Packet::Operator {
+                    op: Operation::Less,
+                    ..
+                } => LengthType::TotalLength,
+                _ => LengthType::Count,
+            };
+            assert_eq!(encode_with(&packet, length_type).unwrap(), hex);
+        }
+        let invalid = Packet::Operator {
+            version: 1,
+            op: Operation::Sum,
+            packets: vec![Packet::literal(8, 1)],
+        };
+        assert_eq!(
+            encode(&invalid),
+            Err(EncodeError::Version {
+                location: Location(vec![0]),
+                version: 8
+            })
+        );
+    }
+
+    // Random packet trees, used to check the parser and evaluator against the encoder and a
+    // reference evaluator. Operators have any number of operands up to `width` unless
+    // `valid_arity` is set.
+    fn packet_strategy(
+        depth: u32,
+        width: usize,
+        valid_arity: bool,
+    ) -> impl Strategy<Value = Packet> {
+        let value = prop_oneof![
+            (0u64..100).prop_map(|value| vec![value as u8]),
+            any::<u64>().prop_map(|value| value.to_le_bytes().to_vec()),
+            prop::collection::vec(any::<u8>(), 1..=20),
+        ];
+        let literal =
+            (0u8..8, value).prop_map(|(version, value)| Packet::Literal { version, value });
+        let op = prop_oneof![
+            Just(Operation::Sum),
+            Just(Operation::Product),
+            Just(Operation::Min),
+            Just(Operation::Max),
+            Just(Operation::Greater),
+            Just(Operation::Less),
+            Just(Operation::Equal),
+        ];
+        literal.prop_recursive(depth, 256, width as u32, move |inner| {
+            (0u8..8, op.clone()).prop_flat_map(move |(version, op)| {
+                let (min, max) = match op.arity() {
+                    (min, max) if valid_arity => (min, max.unwrap_or(width).max(min)),
+                    _ => (0, width),
+                };
+                prop::collection::vec(inner.clone(), min..=max).prop_map(move |packets| {
+                    Packet::Operator {
+                        version,
+                        op,
+                        packets,
+                    }
+                })
+            })
+        })
+    }
+
+    // Straightforward evaluation over u128, failing wherever `evaluate` should.
+    fn reference_evaluate(packet: &Packet) -> Option<usize> {
+        use std::convert::TryFrom;
+        let value: u128 = match packet {
+            Packet::Literal { version: _, value } => {
+                if value.iter().skip(8).any(|&byte| byte != 0) {
+                    return None;
+                }
+                value
+                    .iter()
+                    .take(8)
+                    .rev()
+                    .fold(0, |acc, &byte| acc << 8 | byte as u128)
+            }
+            Packet::Operator {
+                version: _,
+                op,
+                packets,
+            } => {
+                let values = packets
+                    .iter()
+                    .map(|packet| reference_evaluate(packet).map(|value| value as u128))
+                    .collect::<Option<Vec<_>>>()?;
+                match op {
+                    Operation::Sum => values.iter().try_fold(0u128, |acc, &v| {
+                        acc.checked_add(v).filter(|&acc| acc <= usize::MAX as u128)
+                    })?,
+                    Operation::Product => values.iter().try_fold(1u128, |acc, &v| {
+                        acc.checked_mul(v).filter(|&acc| acc <= usize::MAX as u128)
+                    })?,
+                    Operation::Min => *values.iter().min()?,
+                    Operation::Max => *values.iter().max()?,
+                    Operation::Greater => (values[0] > values[1]) as u128,
+                    Operation::Less => (values[0] < values[1]) as u128,
+                    Operation::Equal => (values[0] == values[1]) as u128,
+                }
+            }
+        };
+        usize::try_from(value).ok()
+    }
+
+    proptest! {
+        #[test]
+        fn prop_encode_parse_round_trip(
+            packet in packet_strategy(5, 6, true),
+            modes in prop::collection::vec(any::<bool>(), 1..16),
+        ) {
+            let mut modes = modes.into_iter().cycle();
+            let hex = encode_with(&packet, |_| {
+                if modes.next().unwrap() {
+                    LengthType::TotalLength
+                } else {
+                    LengthType::Count
+                }
+            })
+            .unwrap();
+            prop_assert_eq!(parse(&hex).unwrap(), vec![packet]);
+        }
+
+        #[test]
+        fn prop_evaluate_matches_reference(packet in packet_strategy(5, 6, true)) {
+            prop_assert!(packet.validate().is_ok() || reference_evaluate(&packet).is_none());
+            prop_assert_eq!(packet.evaluate().ok(), reference_evaluate(&packet));
+        }
+
+        #[test]
+        fn prop_simplify_preserves_evaluation(
+            packet in any::<bool>().prop_flat_map(|valid| packet_strategy(4, 5, valid)),
+        ) {
+            let simplified = packet.clone().simplify();
+            prop_assert_eq!(simplified.evaluate().ok(), packet.evaluate().ok());
+            prop_assert_eq!(simplified.validate().is_ok(), packet.validate().is_ok());
+        }
+    }
+
+    #[test]
+    fn test_max_depth() {
+        // Each level is a sum with a single sub-packet, ending in a literal 5.
+        let nested = |depth| {
+            let mut bits = "000000100000000001".repeat(depth);
+            bits.push_str("00010000101");
+            while bits.len() % 8 != 0 {
+                bits.push('0');
+            }
+            (0..bits.len())
+                .step_by(8)
+                .map(|index| {
+                    format!(
+                        "{:02X}",
+                        u8::from_str_radix(&bits[index..index + 8], 2).unwrap()
+                    )
+                })
+                .collect::<String>()
+        };
+        let packets = parse(&nested(MAX_DEPTH)).unwrap();
+        assert_eq!(packets[0].evaluate(), Ok(5));
+        assert!(parse(&nested(MAX_DEPTH + 1)).is_err());
+        assert!(parse_with_max_depth(&nested(3), 2).is_err());
+        assert_eq!(part2(&parse_with_max_depth(&nested(3), 3).unwrap()), 5);
+        // Far deeper than the call stack allows, so dropping must not recurse either.
+        let packets = parse_with_max_depth(&nested(300_000), usize::MAX).unwrap();
+        assert_eq!(part2(&packets), 5);
+        drop(packets);
+
+        // Operators that exceed the depth in a length delimited operator are not skipped.
+        let packet = Packet::Operator {
+            version: 0,
+            op: Operation::Sum,
+            packets: vec![parse(&nested(2)).unwrap().pop().unwrap()],
+        };
+        let hex = encode_with(&packet, |_| LengthType::TotalLength).unwrap();
+        assert_eq!(parse_with_max_depth(&hex, 3).unwrap(), vec![packet]);
+        assert!(parse_with_max_depth(&hex, 2).is_err());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_round_trip() {
+        let packets = parse("38006F45291200").unwrap();
+        let json = serde_json::to_string(&packets[0]).unwrap();
+        assert_eq!(
+            json,
+            r#"{"Operator":{"version":1,"op":"Less","packets":[{"Literal":{"version":6,"value":10}},{"Literal":{"version":2,"value":20}}]}}"#
+        );
+        let decoded: Packet = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded, packets[0]);
+
+        let big: Packet =
+            serde_json::from_str(r#"{"Literal":{"version":0,"value":"18446744073709551616"}}"#)
+                .unwrap();
+        assert_literal!(&big, |0, value| {
+            assert_eq!(*value, vec![0, 0, 0, 0, 0, 0, 0, 0, 1]);
+        });
+        assert_eq!(
+            serde_json::to_string(&big).unwrap(),
+            r#"{"Literal":{"version":0,"value":"18446744073709551616"}}"#
+        );
+
+        // Three groups parse to two bytes, the high one zero, which must survive the trip.
+        let padded = parse("D21028").unwrap().pop().unwrap();
+        assert_literal!(&padded, |6, value| {
+            assert_eq!(*value, vec![5, 0]);
+        });
+        let json = serde_json::to_string(&padded).unwrap();
+        assert_eq!(
+            json,
+            r#"{"Literal":{"version":6,"value":{"value":5,"bytes":2}}}"#
+        );
+        let decoded: Packet = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded, padded);
+        assert_eq!(encode(&decoded).unwrap(), encode(&padded).unwrap());
+        let short = r#"{"Literal":{"version":6,"value":{"value":300,"bytes":1}}}"#;
+        assert!(serde_json::from_str::<Packet>(short).is_err());
+        let huge = r#"{"Literal":{"version":6,"value":{"value":0,"bytes":1000000000000}}}"#;
+        assert!(serde_json::from_str::<Packet>(huge).is_err());
+        let nested =
+            r#"{"Literal":{"version":6,"value":{"value":{"value":5,"bytes":2},"bytes":3}}}"#;
+        assert!(serde_json::from_str::<Packet>(nested).is_err());
+    }
 }
@@ -46,20 +46,14 @@ fn part2(data: &[u64], bits: u32) -> u64 {
     for bit in (0..bits).rev() {
         if !o2_done {
             let most_common = (most_common(&o2_nums, bits) >> bit) & 1;
-            o2_nums = o2_nums
-                .into_iter()
-                .filter(|num| (*num >> bit) & 1 == most_common)
-                .collect();
+            o2_nums.retain(|num| (*num >> bit) & 1 == most_common);
             if o2_nums.len() <= 1 {
                 o2_done = true;
             }
         }
         if !co2_done {
             let least_common = (least_common(most_common(&co2_nums, bits), bits) >> bit) & 1;
-            co2_nums = co2_nums
-                .into_iter()
-                .filter(|num| (*num >> bit) & 1 == least_common)
-                .collect();
+            co2_nums.retain(|num| (*num >> bit) & 1 == least_common);
             if co2_nums.len() <= 1 {
                 co2_done = true;
             }
@@ -34,7 +34,7 @@ fn part2(input: &[Reading]) -> u64 {
         .sum()
 }
 
-fn parse(input: &str) -> Vec<Reading> {
+fn parse(input: &str) -> Vec<Reading<'_>> {
     input
         .lines()
         .map(|line| {
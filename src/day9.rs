@@ -57,19 +57,19 @@ impl<T> Map<T> {
         self.points.len()
     }
     fn columns(&self) -> usize {
-        if let Some(row) = self.points.get(0) {
+        if let Some(row) = self.points.first() {
             row.len()
         } else {
             0
         }
     }
-    fn point(&self, row: usize, col: usize) -> Point<T> {
+    fn point(&self, row: usize, col: usize) -> Point<'_, T> {
         Point {
             index: (row, col),
             map: self,
         }
     }
-    fn points(&self) -> impl Iterator<Item = Point<T>> {
+    fn points(&self) -> impl Iterator<Item = Point<'_, T>> {
         let row = 0..self.rows();
         let col = 0..self.columns();
         row.flat_map(move |row| {
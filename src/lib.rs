@@ -1,17 +1,38 @@
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+#[cfg(feature = "alloc")]
+extern crate alloc;
+
 pub mod bits;
+#[cfg(feature = "std")]
 pub mod day1;
+#[cfg(feature = "std")]
 pub mod day10;
+#[cfg(feature = "std")]
 pub mod day11;
+#[cfg(feature = "std")]
 pub mod day12;
+#[cfg(feature = "std")]
 pub mod day13;
+#[cfg(feature = "std")]
 pub mod day14;
+#[cfg(feature = "std")]
 pub mod day15;
+#[cfg(feature = "std")]
 pub mod day16;
+#[cfg(feature = "std")]
 pub mod day2;
+#[cfg(feature = "std")]
 pub mod day3;
+#[cfg(feature = "std")]
 pub mod day4;
+#[cfg(feature = "std")]
 pub mod day5;
+#[cfg(feature = "std")]
 pub mod day6;
+#[cfg(feature = "std")]
 pub mod day7;
+#[cfg(feature = "std")]
 pub mod day8;
+#[cfg(feature = "std")]
 pub mod day9;